@@ -0,0 +1,541 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::Local;
+use chrono::NaiveDateTime;
+use roxmltree::Document;
+use roxmltree::Node;
+use nmap_xml_parser::Error;
+use nmap_xml_parser::host::Host;
+use nmap_xml_parser::port::PortProtocol;
+use nmap_xml_parser::port::PortState;
+
+use crate::host::AddressesWrapper;
+
+/// Information from the Nmap XML which the nmap_xml_parser crate doesn't expose, for every host in a scan.
+#[derive(Debug,Clone,Default)]
+pub struct ScanDetails {
+	/// The command line that Nmap was run with.
+	pub args: Option<String>,
+	pub scanner: Option<String>,
+	pub version: Option<String>,
+	pub scan_info: Vec<ScanInfo>,
+	pub run_stats: RunStats,
+	/// The original XML from the start of the document up to the first host, without any hosts or progress updates, so that
+	/// new documents can be written with the same `<nmaprun>` element.
+	pub header: String,
+	/// The original `<finished>` element from the runstats, if there was one.
+	pub finished: Option<String>,
+	hosts: Vec<HostDetails>,
+	/// The hosts with each IP address, or with each MAC address for hosts without any IP addresses. Hosts behind the same
+	/// router can share a MAC address, so MAC addresses aren't indexed otherwise.
+	index: HashMap<String,Vec<usize>>
+}
+
+/// The totals from the `<runstats>` element at the end of a scan.
+#[derive(Debug,Clone,Default)]
+pub struct RunStats {
+	pub elapsed: Option<f64>,
+	pub hosts_up: Option<u32>,
+	pub hosts_down: Option<u32>,
+	pub hosts_total: Option<u32>
+}
+
+/// A single `<scaninfo>` element, describing one type of scan and the ports it covered.
+#[derive(Debug,Clone,PartialEq)]
+pub struct ScanInfo {
+	pub scan_type: String,
+	pub protocol: String,
+	pub services: Vec<(u16,u16)>
+}
+
+/// Information about a single host which the nmap_xml_parser crate doesn't expose.
+#[derive(Debug,Clone,Default)]
+pub struct HostDetails {
+	pub services: Vec<ServiceDetails>,
+	pub os_matches: Vec<OsMatch>,
+	pub scripts: Vec<Script>,
+	pub uptime: Option<Uptime>,
+	pub route: Route,
+	pub extraports: Vec<ExtraPorts>,
+	/// The host's addresses, in the same form as `AddressesWrapper::entries`.
+	pub addresses: Vec<String>,
+	/// The original `<host>` element, exactly as it appeared in the scan.
+	pub xml: String
+}
+
+/// The parts of a port's `<service>` and `<script>` elements which the nmap_xml_parser crate doesn't expose.
+#[derive(Debug,Clone)]
+pub struct ServiceDetails {
+	pub protocol: PortProtocol,
+	pub port_number: u16,
+	pub product: Option<String>,
+	pub version: Option<String>,
+	pub extra_info: Option<String>,
+	pub scripts: Vec<Script>
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct OsMatch {
+	pub name: String,
+	pub accuracy: u8,
+	pub classes: Vec<OsClass>
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct OsClass {
+	pub vendor: Option<String>,
+	pub family: Option<String>,
+	pub generation: Option<String>,
+	pub accuracy: u8
+}
+
+/// A summary of ports which Nmap didn't list individually, e.g. "997 closed ports".
+#[derive(Debug,Clone,PartialEq)]
+pub struct ExtraPorts {
+	pub state: PortState,
+	pub count: u32
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Uptime {
+	pub seconds: i64,
+	/// When the host last booted, as a Unix timestamp.
+	pub last_boot: Option<i64>
+}
+
+/// The network distance to a host and the traceroute path taken to reach it.
+#[derive(Debug,Clone,Default)]
+pub struct Route {
+	pub distance: Option<u32>,
+	pub hops: Vec<Hop>
+}
+
+#[derive(Debug,Clone)]
+pub struct Hop {
+	pub ttl: u32,
+	pub address: Option<String>,
+	pub host: Option<String>
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Script {
+	pub id: String,
+	pub output: String
+}
+
+impl ScanDetails {
+	pub fn parse(xml : &str) -> Result<ScanDetails,Error> {
+		let doc = Document::parse(xml)?;
+		let mut details = ScanDetails {
+			args: doc.root_element().attribute("args").map(|x| x.to_string()),
+			scanner: doc.root_element().attribute("scanner").map(|x| x.to_string()),
+			version: doc.root_element().attribute("version").map(|x| x.to_string()),
+			..Default::default()
+		};
+
+		// The header is everything before the root element, its start tag, and the elements which describe the scan as a whole.
+		let root = doc.root_element();
+		let start_tag_end = root.first_child().map(|x| x.range().start).unwrap_or(root.range().end);
+		details.header = format!("{}\n", xml[..start_tag_end].trim_end());
+		
+		for child in doc.root_element().children() {
+			match child.tag_name().name() {
+				"host" => details.push_host(child, xml),
+				"scaninfo" => details.scan_info.push(parse_scaninfo(child)),
+				"runstats" => {
+					details.run_stats = parse_runstats(child);
+					details.finished = child.children().find(|x| x.tag_name().name() == "finished").map(|x| xml[x.range()].to_string());
+				},
+				_ => {}
+			}
+			if matches!(child.tag_name().name(), "scaninfo" | "verbose" | "debugging") {
+				details.header.push_str(&format!("{}\n", &xml[child.range()]));
+			}
+		}
+
+		Ok(details)
+	}
+
+	/// Looks up the details for a host by its addresses. Only a host with exactly the same addresses matches, so that hosts
+	/// which share an address are told apart.
+	pub fn host(&self, host : &Host) -> Option<&HostDetails> {
+		let addresses = AddressesWrapper(host.addresses().cloned().collect()).entries();
+		addresses.iter()
+			.filter_map(|x| self.index.get(x))
+			.flatten()
+			.map(|x| &self.hosts[*x])
+			.find(|x| x.addresses == addresses)
+	}
+
+	fn push_host(&mut self, node : Node, xml : &str) {
+		let mut host = HostDetails { xml: xml[node.range()].to_string(), ..Default::default() };
+
+		for child in node.children() {
+			match child.tag_name().name() {
+				// Addresses are kept as the same strings that AddressesWrapper produces, which normalises IPv6 addresses.
+				"address" => if let Some(addr) = child.attribute("addr") {
+					host.addresses.push(match addr.parse::<std::net::IpAddr>() { Ok(x) => x.to_string(), Err(_) => addr.to_string() });
+				},
+				"ports" => {
+					host.services = child.children().filter(|x| x.tag_name().name() == "port").filter_map(parse_service).collect();
+					host.extraports = child.children().filter(|x| x.tag_name().name() == "extraports").filter_map(parse_extraports).collect();
+				},
+				"os" => host.os_matches = child.children().filter(|x| x.tag_name().name() == "osmatch").map(parse_osmatch).collect(),
+				"hostscript" => host.scripts = parse_scripts(child),
+				"uptime" => host.uptime = parse_uptime(child, node.attribute("endtime")),
+				"distance" => host.route.distance = child.attribute("value").and_then(|x| x.parse::<u32>().ok()),
+				"trace" => host.route.hops = child.children().filter(|x| x.tag_name().name() == "hop").map(parse_hop).collect(),
+				_ => {}
+			}
+		}
+
+		let ips : Vec<&String> = host.addresses.iter().filter(|x| x.parse::<std::net::IpAddr>().is_ok()).collect();
+		let keys = match ips.is_empty() {
+			true => host.addresses.clone(),
+			false => ips.into_iter().cloned().collect()
+		};
+		for key in keys {
+			self.index.entry(key).or_default().push(self.hosts.len());
+		}
+		self.hosts.push(host);
+	}
+}
+
+impl fmt::Display for ScanInfo {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} ({})", self.scan_type, self.protocol)
+	}
+}
+
+impl fmt::Display for RunStats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let count = |x : Option<u32>| x.map(|x| x.to_string()).unwrap_or("?".to_string());
+		write!(f, "{} up, {} down, {} total", count(self.hosts_up), count(self.hosts_down), count(self.hosts_total))
+	}
+}
+
+impl ScanInfo {
+	pub fn covers(&self, protocol : &PortProtocol, port_number : u16) -> bool {
+		self.protocol == protocol.to_string() && self.services.iter().any(|(start, end)| (*start..=*end).contains(&port_number))
+	}
+}
+
+/// Checks whether a scan covered a port. Scans without any `<scaninfo>` are assumed to have covered everything.
+pub fn covers(scan_info : &[ScanInfo], protocol : &PortProtocol, port_number : u16) -> bool {
+	scan_info.is_empty() || scan_info.iter().any(|x| x.covers(protocol, port_number))
+}
+
+/// Returns the ports covered by the left scan but not the right, per protocol, as ranges.
+pub fn coverage_difference(left : &[ScanInfo], right : &[ScanInfo]) -> Vec<(String,Vec<(u16,u16)>)> {
+	let mut protocols : Vec<String> = left.iter().map(|x| x.protocol.clone()).collect();
+	protocols.dedup();
+	
+	let mut output : Vec<(String,Vec<(u16,u16)>)> = Vec::new();
+	for protocol in protocols {
+		let covered = |scan_info : &[ScanInfo], port : u16| scan_info.iter().any(|x| x.protocol == protocol && x.services.iter().any(|(start, end)| (*start..=*end).contains(&port)));
+		let only_left : Vec<u16> = (0..=u16::MAX).filter(|x| covered(left, *x) && !covered(right, *x)).collect();
+		if !only_left.is_empty() {
+			output.push((protocol.clone(), to_ranges(&only_left)));
+		}
+	}
+	output
+}
+
+/// Formats a list of port ranges the way Nmap's `-p` option expects them, e.g. "1-1000,3389".
+pub fn format_ranges(ranges : &[(u16,u16)]) -> String {
+	let output : Vec<String> = ranges.iter().map(|(start, end)| match start == end {
+		true => start.to_string(),
+		false => format!("{}-{}", start, end)
+	}).collect();
+	output.join(",")
+}
+
+/// Parses a list of ports and ranges in the format Nmap's `-p` option expects, e.g. "1-1000,3389".
+pub fn parse_ranges(list : &str) -> Result<Vec<(u16,u16)>,String> {
+	list.split(',').map(|x| {
+		let parse = |port : &str| port.trim().parse::<u16>().map_err(|_| format!("invalid port '{}'", port.trim()));
+		match x.split_once('-') {
+			Some((start, end)) => match (parse(start)?, parse(end)?) {
+				(start, end) if start <= end => Ok((start, end)),
+				_ => Err(format!("invalid port range '{}'", x.trim()))
+			},
+			None => parse(x).map(|port| (port, port))
+		}
+	}).collect()
+}
+
+/// Collapses a sorted list of ports into contiguous ranges.
+pub fn to_ranges(ports : &[u16]) -> Vec<(u16,u16)> {
+	let mut output : Vec<(u16,u16)> = Vec::new();
+	for port in ports {
+		match output.last_mut() {
+			Some((_, end)) if end.checked_add(1) == Some(*port) => *end = *port,
+			_ => output.push((*port, *port))
+		}
+	}
+	output
+}
+
+impl HostDetails {
+	/// Returns the service details for a specific port, if the scan recorded any.
+	pub fn service(&self, protocol : &PortProtocol, port_number : u16) -> Option<&ServiceDetails> {
+		self.services.iter().find(|x| &x.protocol == protocol && x.port_number == port_number)
+	}
+}
+
+impl ServiceDetails {
+	/// Returns the product, version and extra info as a single string, e.g. "OpenSSH 8.9 (protocol 2.0)".
+	pub fn product_version(&self) -> String {
+		let mut output : Vec<String> = Vec::new();
+		if let Some(product) = &self.product { output.push(product.clone()); }
+		if let Some(version) = &self.version { output.push(version.clone()); }
+		if let Some(extra_info) = &self.extra_info { output.push(format!("({})", extra_info)); }
+		output.join(" ")
+	}
+}
+
+impl OsClass {
+	/// The parts of the class which identify the OS, ignoring accuracy.
+	pub fn key(&self) -> (&Option<String>, &Option<String>, &Option<String>) {
+		(&self.vendor, &self.family, &self.generation)
+	}
+}
+
+impl Route {
+	pub fn is_empty(&self) -> bool {
+		self.distance.is_none() && self.hops.is_empty()
+	}
+	
+	/// Checks whether the distance or the sequence of hop addresses differ, ignoring round trip times.
+	pub fn differs(&self, other : &Route) -> bool {
+		let addresses = |route : &Route| route.hops.iter().map(|x| (x.ttl, x.address.clone())).collect::<Vec<(u32, Option<String>)>>();
+		self.distance != other.distance || addresses(self) != addresses(other)
+	}
+}
+
+impl fmt::Display for Route {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.distance {
+			Some(1) => write!(f, "1 hop")?,
+			Some(x) => write!(f, "{} hops", x)?,
+			None => write!(f, "unknown distance")?
+		};
+		
+		if !self.hops.is_empty() {
+			let hops : Vec<String> = self.hops.iter().map(|x| {
+				let address = x.address.clone().unwrap_or("*".to_string());
+				match &x.host { Some(host) => format!("{} ({})", host, address), None => address }
+			}).collect();
+			write!(f, " via {}", hops.join(" -> "))?;
+		}
+		
+		Ok(())
+	}
+}
+
+impl fmt::Display for OsMatch {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} ({}%)", self.name, self.accuracy)?;
+		if let Some(class) = self.classes.first() {
+			let parts : Vec<&str> = [&class.vendor, &class.family, &class.generation].into_iter().flatten().map(|x| x.as_str()).collect();
+			write!(f, " [{}]", parts.join(" "))?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Display for ExtraPorts {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} {}", self.count, self.state)
+	}
+}
+
+/// Parses a port state as Nmap writes it, which doesn't always match nmap_xml_parser's names.
+pub fn parse_port_state(state : &str) -> Option<PortState> {
+	match state {
+		"closed|filtered" => Some(PortState::CloseFiltered),
+		_ => PortState::from_str(state).ok()
+	}
+}
+
+fn parse_scaninfo(node : Node) -> ScanInfo {
+	// Services are listed as a comma-separated list of ports and ranges, e.g. "1-1000,1433,3389".
+	let services = node.attribute("services").unwrap_or_default().split(',').filter_map(|x| {
+		match x.split_once('-') {
+			Some((start, end)) => Some((start.trim().parse::<u16>().ok()?, end.trim().parse::<u16>().ok()?)),
+			None => x.trim().parse::<u16>().ok().map(|x| (x, x))
+		}
+	}).collect();
+	
+	ScanInfo {
+		scan_type: node.attribute("type").unwrap_or_default().to_string(),
+		protocol: node.attribute("protocol").unwrap_or_default().to_string(),
+		services
+	}
+}
+
+fn parse_runstats(node : Node) -> RunStats {
+	let mut run_stats = RunStats::default();
+	for child in node.children() {
+		match child.tag_name().name() {
+			"finished" => run_stats.elapsed = child.attribute("elapsed").and_then(|x| x.parse::<f64>().ok()),
+			"hosts" => {
+				run_stats.hosts_up = child.attribute("up").and_then(|x| x.parse::<u32>().ok());
+				run_stats.hosts_down = child.attribute("down").and_then(|x| x.parse::<u32>().ok());
+				run_stats.hosts_total = child.attribute("total").and_then(|x| x.parse::<u32>().ok());
+			},
+			_ => {}
+		}
+	}
+	run_stats
+}
+
+fn parse_extraports(node : Node) -> Option<ExtraPorts> {
+	Some(ExtraPorts {
+		state: parse_port_state(node.attribute("state")?)?,
+		count: node.attribute("count")?.parse::<u32>().ok()?
+	})
+}
+
+fn parse_service(node : Node) -> Option<ServiceDetails> {
+	let protocol = PortProtocol::from_str(node.attribute("protocol")?).ok()?;
+	let port_number = node.attribute("portid")?.parse::<u16>().ok()?;
+	let service = node.children().find(|x| x.tag_name().name() == "service");
+
+	Some(ServiceDetails {
+		protocol,
+		port_number,
+		product: service.and_then(|x| x.attribute("product")).map(|x| x.to_string()),
+		version: service.and_then(|x| x.attribute("version")).map(|x| x.to_string()),
+		extra_info: service.and_then(|x| x.attribute("extrainfo")).map(|x| x.to_string()),
+		scripts: parse_scripts(node)
+	})
+}
+
+fn parse_osmatch(node : Node) -> OsMatch {
+	OsMatch {
+		name: node.attribute("name").unwrap_or_default().to_string(),
+		accuracy: node.attribute("accuracy").and_then(|x| x.parse::<u8>().ok()).unwrap_or(0),
+		classes: node.children().filter(|x| x.tag_name().name() == "osclass").map(|x| {
+			OsClass {
+				vendor: x.attribute("vendor").map(|x| x.to_string()),
+				family: x.attribute("osfamily").map(|x| x.to_string()),
+				generation: x.attribute("osgen").map(|x| x.to_string()),
+				accuracy: x.attribute("accuracy").and_then(|x| x.parse::<u8>().ok()).unwrap_or(0)
+			}
+		}).collect()
+	}
+}
+
+fn parse_uptime(node : Node, host_end_time : Option<&str>) -> Option<Uptime> {
+	let seconds = node.attribute("seconds")?.parse::<i64>().ok()?;
+	
+	// Prefer working back from when the host finished scanning, since the `lastboot` string is in the scanner's local time.
+	let last_boot = match host_end_time.and_then(|x| x.parse::<i64>().ok()) {
+		Some(end_time) => Some(end_time - seconds),
+		None => node.attribute("lastboot")
+			.and_then(|x| NaiveDateTime::parse_from_str(x, "%a %b %e %H:%M:%S %Y").ok())
+			.and_then(|x| x.and_local_timezone(Local).earliest())
+			.map(|x| x.timestamp())
+	};
+	
+	Some(Uptime { seconds, last_boot })
+}
+
+fn parse_hop(node : Node) -> Hop {
+	Hop {
+		ttl: node.attribute("ttl").and_then(|x| x.parse::<u32>().ok()).unwrap_or(0),
+		address: node.attribute("ipaddr").map(|x| x.to_string()),
+		host: node.attribute("host").map(|x| x.to_string())
+	}
+}
+
+fn parse_scripts(node : Node) -> Vec<Script> {
+	node.children().filter(|x| x.tag_name().name() == "script").map(|x| {
+		Script {
+			id: x.attribute("id").unwrap_or_default().to_string(),
+			output: x.attribute("output").unwrap_or_default().to_string()
+		}
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	use crate::scan::Scan;
+	
+	fn scan_info(scaninfo : &str) -> Vec<ScanInfo> {
+		ScanDetails::parse(&nmaprun("nmap 10.0.0.1", 1700000000, scaninfo)).unwrap().scan_info
+	}
+	
+	#[test]
+	fn parses_scaninfo_services() {
+		let info = scan_info(r#"<scaninfo type="syn" protocol="tcp" numservices="1001" services="1-1000,3389"/><scaninfo type="udp" protocol="udp" numservices="2" services="53,161"/>"#);
+		assert_eq!(info.len(), 2);
+		assert_eq!((info[0].scan_type.as_str(), info[0].protocol.as_str()), ("syn", "tcp"));
+		assert_eq!(info[0].services, vec![(1, 1000), (3389, 3389)]);
+		assert_eq!(info[1].services, vec![(53, 53), (161, 161)]);
+		
+		assert!(covers(&info, &PortProtocol::Tcp, 1000));
+		assert!(!covers(&info, &PortProtocol::Tcp, 1001));
+		assert!(covers(&info, &PortProtocol::Udp, 161));
+		assert!(!covers(&info, &PortProtocol::Udp, 22));
+		assert!(covers(&[], &PortProtocol::Sctp, 1));
+	}
+	
+	#[test]
+	fn finds_ports_only_one_scan_covered() {
+		let left = scan_info(r#"<scaninfo type="syn" protocol="tcp" numservices="65535" services="1-65535"/><scaninfo type="udp" protocol="udp" numservices="1" services="53"/>"#);
+		let right = scan_info(r#"<scaninfo type="syn" protocol="tcp" numservices="1001" services="1-1000,3389"/>"#);
+		
+		assert_eq!(coverage_difference(&left, &right), vec![
+			("tcp".to_string(), vec![(1001, 3388), (3390, 65535)]),
+			("udp".to_string(), vec![(53, 53)])
+		]);
+		assert_eq!(coverage_difference(&right, &left), vec![]);
+		assert_eq!(coverage_difference(&left, &left), vec![]);
+	}
+	
+	#[test]
+	fn parses_port_ranges() {
+		assert_eq!(parse_ranges("1-1000,3389"), Ok(vec![(1, 1000), (3389, 3389)]));
+		assert_eq!(parse_ranges(" 22 , 80-81 "), Ok(vec![(22, 22), (80, 81)]));
+		assert_eq!(parse_ranges("0-65535"), Ok(vec![(0, 65535)]));
+		assert_eq!(parse_ranges("80-80"), Ok(vec![(80, 80)]));
+		assert!(parse_ranges("1000-1").is_err());
+		assert!(parse_ranges("65536").is_err());
+		assert!(parse_ranges("http").is_err());
+		assert!(parse_ranges("").is_err());
+	}
+	
+	#[test]
+	fn formats_port_ranges() {
+		assert_eq!(to_ranges(&[21, 22, 23, 80, 443, 65534, 65535]), vec![(21, 23), (80, 80), (443, 443), (65534, 65535)]);
+		assert_eq!(format_ranges(&to_ranges(&[21, 22, 23, 80])), "21-23,80");
+		assert_eq!(format_ranges(&[]), "");
+	}
+	
+	#[test]
+	fn finds_hosts_by_all_of_their_addresses() {
+		let xml = nmaprun("nmap 10.0.0.0/24", 1700000000, &[
+			host(&["10.0.0.1", "00:11:22:33:44:55"], None, &open(&[22])),
+			host(&["10.0.0.2", "00:11:22:33:44:55"], None, &open(&[80])),
+			host(&["00:11:22:33:44:66"], None, ""),
+			// Two hosts with the same IP address, e.g. from a target given twice, are still told apart by their other addresses.
+			host(&["10.0.0.3", "00:11:22:33:44:77"], None, ""),
+			host(&["10.0.0.3", "00:11:22:33:44:88"], None, "")
+		].concat());
+		let scan = Scan::parse(&xml).unwrap();
+		
+		for host in scan.results.hosts() {
+			let details = scan.details.host(host).unwrap();
+			assert_eq!(details.addresses, AddressesWrapper(host.addresses().cloned().collect()).entries());
+			assert!(details.xml.contains(&format!("addr=\"{}\"", details.addresses[0])));
+			assert!(details.xml.contains(&format!("addr=\"{}\"", details.addresses[details.addresses.len() - 1])));
+		}
+		assert_eq!(scan.details.hosts.len(), 5);
+	}
+}
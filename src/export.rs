@@ -0,0 +1,90 @@
+use std::fs;
+
+use nmap_xml_parser::host::HostState;
+
+use crate::host::DeltaKind;
+use crate::host::HostDelta;
+use crate::host::HostWrapper;
+use crate::scan::Scan;
+
+/// Builds an Nmap XML document with only the new and changed hosts from the right-hand scan. The document keeps the scan's
+/// original `<nmaprun>` element and host elements, so that other Nmap tooling can read it, and only the host counts in the
+/// runstats are recalculated. Fails if a host's element can't be found, rather than leaving the host out of the document.
+pub fn changed_hosts_xml(right : &Scan, deltas : &[HostDelta]) -> Result<String,String> {
+	let hosts : Vec<_> = deltas.iter()
+		.filter(|x| matches!(x.kind(), DeltaKind::New | DeltaKind::Changed))
+		.map(|x| match right.details.host(x.host()) {
+			Some(details) => Ok((x.host(), &details.xml)),
+			None => Err(format!("couldn't find the XML for {}", HostWrapper(x.host().clone()).get_title()))
+		})
+		.collect::<Result<_,String>>()?;
+	
+	let mut output = right.details.header.clone();
+	for (_, xml) in &hosts {
+		output.push_str(&format!("{}\n", xml));
+	}
+	
+	let up = hosts.iter().filter(|(host, _)| host.status.state == HostState::Up).count();
+	let down = hosts.iter().filter(|(host, _)| host.status.state == HostState::Down).count();
+	// The runstats have to end with a <finished> element, so one is made up if the scan didn't have one.
+	let finished = match &right.details.finished {
+		Some(x) => x.clone(),
+		None => format!("<finished time=\"{}\"/>", right.results.scan_end_time.unwrap_or(right.results.scan_start_time))
+	};
+	output.push_str(&format!("<runstats>{}<hosts up=\"{}\" down=\"{}\" total=\"{}\"/></runstats>\n</nmaprun>\n", finished, up, down, hosts.len()));
+	
+	Ok(output)
+}
+
+/// Writes the new and changed hosts from the right-hand scan to a file, in Nmap's XML format.
+pub fn write_changed_hosts(path : &str, right : &Scan, deltas : &[HostDelta]) -> Result<(),String> {
+	fs::write(path, changed_hosts_xml(right, deltas)?).map_err(|e| format!("couldn't write '{}': {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	use crate::host::DiffOptions;
+	
+	#[test]
+	fn writes_the_new_and_changed_hosts_as_nmap_xml() {
+		let down = r#"<host><status state="down" reason="no-response" reason_ttl="0"/><address addr="10.0.0.9" addrtype="ipv4"/></host>"#;
+		let left = scan(&[host(&["10.0.0.1"], None, &open(&[22])), host(&["10.0.0.2"], None, &open(&[22])), host(&["10.0.0.3"], None, "")].concat());
+		let right = scan(&[host(&["10.0.0.1"], None, &open(&[22, 80])), host(&["10.0.0.2"], None, &open(&[22])), host(&["10.0.0.4"], None, ""), down.to_string()].concat());
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		
+		let export = Scan::parse(&changed_hosts_xml(&right, &deltas).unwrap()).unwrap();
+		let addresses : Vec<String> = export.results.hosts().map(|x| HostWrapper(x.clone()).get_title()).collect();
+		assert_eq!(addresses, vec!["<no hostname> (10.0.0.4)", "<no hostname> (10.0.0.9)", "<no hostname> (10.0.0.1)"]);
+		let stats = &export.details.run_stats;
+		assert_eq!((stats.hosts_up, stats.hosts_down, stats.hosts_total), (Some(2), Some(1), Some(3)));
+		// The original run's details are kept.
+		assert_eq!(export.details.args, right.details.args);
+		assert_eq!(export.details.scan_info, right.details.scan_info);
+		assert_eq!(export.results.scan_start_time, right.results.scan_start_time);
+		assert_eq!(export.details.host(export.results.hosts().last().unwrap()).unwrap().xml, right.details.host(right.results.hosts().next().unwrap()).unwrap().xml);
+	}
+	
+	#[test]
+	fn writes_an_empty_run_when_nothing_changed() {
+		let left = scan(&host(&["10.0.0.1"], None, &open(&[22])));
+		let deltas = HostDelta::from_scans(&left, &left, &DiffOptions::default());
+		
+		let export = Scan::parse(&changed_hosts_xml(&left, &deltas).unwrap()).unwrap();
+		assert_eq!(export.results.hosts().count(), 0);
+		assert_eq!(export.details.run_stats.hosts_total, Some(0));
+	}
+	
+	#[test]
+	fn exports_the_right_host_when_hosts_share_a_mac_address() {
+		// Hosts behind a router are all seen with the router's MAC address.
+		let left = scan(&host(&["00:11:22:33:44:55", "10.0.1.1"], None, &open(&[22])));
+		let right = scan(&[host(&["00:11:22:33:44:55", "10.0.1.1"], None, &open(&[22, 80])), host(&["00:11:22:33:44:55", "10.0.1.2"], None, &open(&[443]))].concat());
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		
+		let export = changed_hosts_xml(&right, &deltas).unwrap();
+		assert_eq!(export.matches("addr=\"10.0.1.1\"").count(), 1, "{}", export);
+		assert_eq!(export.matches("addr=\"10.0.1.2\"").count(), 1, "{}", export);
+	}
+}
@@ -0,0 +1,62 @@
+//! Builders for fragments of Nmap XML, so that tests parse their scans the same way real ones are.
+
+use crate::scan::Scan;
+
+/// Wraps elements in just enough of an Nmap run to be parsed, run with the given command line at the given time.
+pub fn nmaprun(args : &str, start : i64, elements : &str) -> String {
+	format!(r#"<?xml version="1.0"?>
+<nmaprun scanner="nmap" args="{}" start="{}" version="7.94">
+{}
+<runstats><finished time="{}" timestr="" elapsed="100" summary="" exit="success"/><hosts up="1" down="0" total="1"/></runstats>
+</nmaprun>
+"#, args, start, elements, start + 100)
+}
+
+/// Wraps host elements in a scan of every TCP port.
+pub fn scan(hosts : &str) -> Scan {
+	scan_covering("1-65535", hosts)
+}
+
+/// Like `scan`, but with the TCP ports the scan covered given in the format of Nmap's `-p` option.
+pub fn scan_covering(services : &str, hosts : &str) -> Scan {
+	let scaninfo = format!(r#"<scaninfo type="syn" protocol="tcp" numservices="1000" services="{}"/>"#, services);
+	Scan::parse(&nmaprun(&format!("nmap -p {} 10.0.0.0/24", services), 1700000000, &format!("{}\n{}", scaninfo, hosts))).unwrap()
+}
+
+/// Returns a host element which is up, with the given IP addresses, hostname and port elements.
+pub fn host(addresses : &[&str], hostname : Option<&str>, ports : &str) -> String {
+	host_with(addresses, hostname, ports, "")
+}
+
+/// Like `host`, but with extra elements after the ports, such as `<os>` or `<trace>`.
+pub fn host_with(addresses : &[&str], hostname : Option<&str>, ports : &str, extra : &str) -> String {
+	let addresses : String = addresses.iter().map(|x| {
+		let family = match x.matches(':').count() {
+			0 => "ipv4",
+			5 => "mac",
+			_ => "ipv6"
+		};
+		format!(r#"<address addr="{}" addrtype="{}"/>"#, x, family)
+	}).collect();
+	let hostnames = hostname.map(|x| format!(r#"<hostnames><hostname name="{}" type="PTR"/></hostnames>"#, x)).unwrap_or_default();
+	format!(r#"<host starttime="1700000001" endtime="1700000002"><status state="up" reason="syn-ack" reason_ttl="0"/>{}{}<ports>{}</ports>{}</host>"#, addresses, hostnames, ports, extra)
+}
+
+/// Returns a TCP port element in the given state.
+pub fn port(number : u16, state : &str) -> String {
+	format!(r#"<port protocol="tcp" portid="{}"><state state="{}" reason="syn-ack" reason_ttl="64"/></port>"#, number, state)
+}
+
+/// Returns open TCP port elements for each of the port numbers.
+pub fn open(numbers : &[u16]) -> String {
+	numbers.iter().map(|x| port(*x, "open")).collect()
+}
+
+/// Returns an open TCP port element running a service, e.g. ("ssh", "OpenSSH", "9.6").
+pub fn service(number : u16, name : &str, product : &str, version : &str) -> String {
+	format!(r#"<port protocol="tcp" portid="{}"><state state="open" reason="syn-ack" reason_ttl="64"/><service name="{}" product="{}" version="{}" method="probed" conf="10"/></port>"#, number, name, product, version)
+}
+
+pub fn extraports(state : &str, count : u32) -> String {
+	format!(r#"<extraports state="{}" count="{}"><extrareasons reason="resets" count="{}"/></extraports>"#, state, count, count)
+}
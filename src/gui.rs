@@ -1,6 +1,13 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-use std::fs;
+use std::fmt;
+use std::cmp::Ordering;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::TryRecvError;
+use std::thread;
 use chrono::DateTime;
 use eframe::egui;
 use egui::Layout;
@@ -8,16 +15,39 @@ use egui::Align;
 use egui::Grid;
 use egui::ScrollArea;
 use egui::widgets::Separator;
+use egui_extras::TableBuilder;
+use egui_extras::Column;
 use rfd::FileDialog;
 
-use crate::host::HostDiff;
+use crate::host::Change;
 use crate::host::HostDelta;
+use crate::host::DeltaKind;
+use crate::host::DiffOptions;
+use crate::host::StateClasses;
+use crate::host::DownHostPolicy;
+use crate::host::format_timestamp;
 use crate::host::HostWrapper;
+use crate::host::PortWrapper;
+use crate::host::AddressWrapper;
 use crate::host::PortsWrapper;
 use crate::host::AddressesWrapper;
 use crate::host::HostnamesWrapper;
+use crate::details::HostDetails;
+use crate::palette::Palette;
+use crate::palette::Meaning;
+use crate::palette::Scheme;
+use crate::scan::Scan;
+use crate::scan::ScanComparison;
+use crate::scan::Error as ScanError;
+use crate::scope::Scope;
+use crate::history::HistoryStore;
+use crate::scope::parse_list;
 use nmap_xml_parser::NmapResults;
 use nmap_xml_parser::host::Host;
+use nmap_xml_parser::host::HostStatus;
+use nmap_xml_parser::host::Address;
+use nmap_xml_parser::port::Port;
+use nmap_xml_parser::port::PortProtocol;
 
 pub fn run_gui() -> eframe::Result {
 	let mut options = eframe::NativeOptions::default();
@@ -33,10 +63,26 @@ pub fn run_gui() -> eframe::Result {
 struct NDiffApp {
 			left_path: String,
 			right_path: String,
-			left_scan: Option<NmapResults>,
-			right_scan: Option<NmapResults>,
+			left_scan: Option<Arc<Scan>>,
+			right_scan: Option<Arc<Scan>>,
 			deltas: Vec<HostDelta>,
+			rows: Vec<DeltaRow>,
+			host_rows: Vec<HostRow>,
+			host_order: Vec<usize>,
+			view: View,
+			sort_column: SortColumn,
+			sort_ascending: bool,
+			selected: Option<usize>,
+			scheme: Scheme,
+			options: DiffOptions,
+			comparison: Option<ScanComparison>,
+			include: String,
+			exclude: String,
+			history_path: String,
+			history_host: String,
+			history_lines: Vec<String>,
 			processed: bool,
+			pending: Option<Receiver<Processed>>,
 			err_msg : Option<String>
 }
 
@@ -48,7 +94,23 @@ impl Default for NDiffApp {
 			left_scan: None,
 			right_scan: None,
 			deltas: Vec::new(),
+			rows: Vec::new(),
+			host_rows: Vec::new(),
+			host_order: Vec::new(),
+			view: View::SideBySide,
+			sort_column: SortColumn::Address,
+			sort_ascending: true,
+			selected: None,
+			scheme: Scheme::Default,
+			options: DiffOptions::default(),
+			comparison: None,
+			include: String::new(),
+			exclude: String::new(),
+			history_path: String::new(),
+			history_host: String::new(),
+			history_lines: Vec::new(),
 			processed: false,
+			pending: None,
 			err_msg: None
 		}
 	}
@@ -58,6 +120,18 @@ impl eframe::App for NDiffApp {
 	fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
 		let max_width : f32 = ui.ctx().content_rect().max.x;
 	
+		if let Some(index) = self.selected {
+			let mut open = true;
+			egui::Panel::right("host_details").resizable(true).default_size(max_width / 3.0).show_inside(ui, |ui| {
+				ui.horizontal(|ui| {
+					ui.heading("Host Details");
+					if ui.button("Close").clicked() { open = false; }
+				});
+				ScrollArea::vertical().show(ui, |ui| { self.render_details(index, ui) });
+			});
+			if !open { self.selected = None; }
+		}
+	
 		egui::CentralPanel::default().show_inside(ui, |ui| {
 			ui.with_layout(Layout::left_to_right(Align::TOP), |ui| {
 				ui.with_layout(Layout::top_down(Align::TOP), |ui| {
@@ -65,7 +139,7 @@ impl eframe::App for NDiffApp {
 					ui.heading("First Scan");
 					ui.add_space(32.0);
 					match &self.left_scan {
-						Some(_) => { ui.label(format!("Scan loaded!\nScan Path: {}\nScan Time: {}", &self.left_path, get_time(&self.left_scan.clone().unwrap()))); },
+						Some(scan) => { ui.label(format!("Scan loaded!\nScan Path: {}\nScan Time: {}", &self.left_path, get_time(&scan.results))); },
 						None => { 
 							ui.label("Waiting for a scan...");
 							if ui.button("Open file...").clicked() && let Some(path) = FileDialog::new().pick_file() {
@@ -81,7 +155,7 @@ impl eframe::App for NDiffApp {
 					ui.heading("Second Scan");
 					ui.add_space(32.0);
 					match &self.right_scan {
-						Some(_) => { ui.label(format!("Scan loaded!\nScan Path: {}\nScan Time: {}", &self.right_path, get_time(&self.right_scan.clone().unwrap()))); },
+						Some(scan) => { ui.label(format!("Scan loaded!\nScan Path: {}\nScan Time: {}", &self.right_path, get_time(&scan.results))); },
 						None => { 
 							ui.label("Waiting for a scan...");
 							if ui.button("Open file...").clicked() && let Some(path) = FileDialog::new().pick_file() {
@@ -94,179 +168,714 @@ impl eframe::App for NDiffApp {
 			});
 			
 			if self.left_scan.is_some() && self.right_scan.is_some() && !self.processed {
-				self.deltas = HostDelta::from_scans(&self.left_scan.clone().unwrap(), &self.right_scan.clone().unwrap());
-				self.processed = true;
+				self.process(ui.ctx());
+			}
+			if self.pending.is_some() {
+				ui.horizontal(|ui| {
+					ui.spinner();
+					ui.label("Diffing the scans...");
+				});
 			}
 			
 			if let Some(err_msg) = &self.err_msg {
-				let err_color = egui::Color32::from_rgb(0x80, 0x0, 0x0);
+				let err_color = Palette::new(self.scheme, ui.visuals().dark_mode).removed;
 				ui.label(egui::RichText::new(err_msg).color(err_color));
 			}
 			
+			if self.processed && let Some(comparison) = &self.comparison {
+				let palette = Palette::new(self.scheme, ui.visuals().dark_mode);
+				egui::CollapsingHeader::new("Scan metadata").show(ui, |ui| {
+					Grid::new("scan_metadata").striped(true).show(ui, |ui| {
+						for (label, left, right) in &comparison.rows {
+							let meaning = match left == right { true => Meaning::Unchanged, false => Meaning::Changed };
+							ui.strong(format!("{} {}", meaning.marker(), label));
+							ui.label(egui::RichText::new(left).color(palette.color(meaning)));
+							ui.label(egui::RichText::new(right).color(palette.color(meaning)));
+							ui.end_row();
+						}
+					});
+				});
+				for warning in &comparison.warnings {
+					ui.label(egui::RichText::new(format!("Warning: {}", warning)).color(palette.changed));
+				}
+			}
+			
 			if self.processed {
-				self.render_deltas(ui);
+				ui.horizontal(|ui| {
+					ui.selectable_value(&mut self.view, View::SideBySide, "Side by side");
+					ui.selectable_value(&mut self.view, View::Table, "Table");
+					ui.selectable_value(&mut self.view, View::History, "History");
+					ui.separator();
+					egui::ComboBox::from_label("Colours").selected_text(self.scheme.to_string()).show_ui(ui, |ui| {
+						for scheme in Scheme::ALL {
+							ui.selectable_value(&mut self.scheme, scheme, scheme.to_string());
+						}
+					});
+					egui::widgets::global_theme_preference_buttons(ui);
+					ui.separator();
+					// Changing the options means the deltas need to be recalculated on the next frame.
+					let mut exposure_only = self.options.state_classes == StateClasses::exposure();
+					let routes_changed = ui.checkbox(&mut self.options.compare_routes, "Compare routes").changed();
+					let exposure_changed = ui.checkbox(&mut exposure_only, "Exposure changes only").changed();
+					let mut down_present = self.options.down_hosts == DownHostPolicy::Present;
+					let down_changed = ui.checkbox(&mut down_present, "Count down hosts as present").changed();
+					if down_changed {
+						self.options.down_hosts = match down_present { true => DownHostPolicy::Present, false => DownHostPolicy::Absent };
+					}
+					if exposure_changed {
+						self.options.state_classes = match exposure_only { true => StateClasses::exposure(), false => StateClasses::default() };
+					}
+					if routes_changed || exposure_changed || down_changed {
+						self.reprocess();
+					}
+				});
+				
+				ui.horizontal(|ui| {
+					ui.label("Include:");
+					ui.add(egui::TextEdit::singleline(&mut self.include).hint_text("e.g. 10.0.0.0/24, 2001:db8::/32").desired_width(max_width / 4.0));
+					ui.label("Exclude:");
+					ui.add(egui::TextEdit::singleline(&mut self.exclude).desired_width(max_width / 4.0));
+					if ui.button("Apply").clicked() {
+						self.apply_scope();
+					}
+				});
+				
+				match self.view {
+					View::SideBySide => self.render_deltas(ui),
+					View::Table => self.render_table(ui),
+					View::History => self.render_history(ui)
+				};
 			}
 		});
 	}
 }
 
 impl NDiffApp {
-	fn load_scan(&mut self, path: String, _ui: &mut egui::Ui) -> Option<NmapResults> {
+	fn load_scan(&mut self, path: String, _ui: &mut egui::Ui) -> Option<Arc<Scan>> {
 		self.err_msg = None;
 	
-		let content = match fs::read_to_string(path) {
-			Ok(x) => x,
-			Err(_) => {
-				self.err_msg = Some(format!("Error reading the specified file."));
-				return None;
+		match Scan::load(&path) {
+			Ok(x) => Some(Arc::new(x)),
+			Err(ScanError::FileRead(_)) => {
+				self.err_msg = Some("Error reading the specified file.".to_string());
+				None
+			},
+			Err(ScanError::FileParse(_)) => {
+				self.err_msg = Some("Error parsing the specified file as an XML Nmap scan.".to_string());
+				None
+			}
+		}
+	}
+	
+	/// Diffs the loaded scans on a background thread, so that large scans don't freeze the window, and picks up the result
+	/// once it's ready.
+	fn process(&mut self, ctx: &egui::Context) {
+		let receiver = match self.pending.take() {
+			Some(x) => x,
+			None => {
+				let (sender, receiver) = mpsc::channel();
+				let (left, right) = (Arc::clone(self.left_scan.as_ref().unwrap()), Arc::clone(self.right_scan.as_ref().unwrap()));
+				let (options, sort_column, sort_ascending) = (self.options.clone(), self.sort_column, self.sort_ascending);
+				let ctx = ctx.clone();
+				thread::spawn(move || {
+					// The receiver is gone if the options changed in the meantime, in which case the result isn't needed.
+					if sender.send(Processed::from_scans(&left, &right, &options, sort_column, sort_ascending)).is_ok() {
+						ctx.request_repaint();
+					}
+				});
+				receiver
 			}
 		};
 		
-		match NmapResults::parse(&content) {
-			Ok(x) => Some(x),
-			Err(_) => {
-				self.err_msg = Some(format!("Error parsing the specified file as an XML Nmap scan."));
-				return None;
+		match receiver.try_recv() {
+			Ok(processed) => {
+				self.deltas = processed.deltas;
+				self.rows = processed.rows;
+				self.host_rows = processed.host_rows;
+				self.host_order = processed.host_order;
+				self.comparison = Some(processed.comparison);
+				self.processed = true;
+			},
+			Err(TryRecvError::Empty) => self.pending = Some(receiver),
+			Err(TryRecvError::Disconnected) => {
+				self.err_msg = Some("Failed to diff the scans.".to_string());
+				self.deltas = Vec::new();
+				self.rows = Vec::new();
+				self.host_rows = Vec::new();
+				self.host_order = Vec::new();
+				self.comparison = None;
+				self.processed = true;
 			}
 		}
 	}
 	
-	fn render_deltas(&mut self, ui: &mut egui::Ui) {
-		ScrollArea::vertical().show(ui, |ui| {
-			Grid::new("delta_grid").show(ui, |ui| {
-				for delta in &self.deltas {
-					match delta {
-						HostDelta::Changed(diff) => { 
-							ui.with_layout(Layout::top_down(Align::TOP), |ui| { self.render_changed(diff, true, ui) }); 
-							ui.with_layout(Layout::top_down(Align::TOP), |ui| { self.render_changed(diff, false, ui) }); 
-						},
-						HostDelta::Unchanged(host) => {
-							ui.with_layout(Layout::top_down(Align::TOP), |ui| { self.render_unchanged(host, ui) }); 
-							ui.with_layout(Layout::top_down(Align::TOP), |ui| { self.render_unchanged(host, ui) }); 
-						},
-						HostDelta::Gone(host) => {
-							ui.with_layout(Layout::top_down(Align::TOP), |ui| { self.render_gone(host, true, ui) }); 
-							ui.with_layout(Layout::top_down(Align::TOP), |ui| { self.render_gone(host, false, ui) }); 						
-						},
-						HostDelta::New(host) => {
-							ui.with_layout(Layout::top_down(Align::TOP), |ui| { self.render_new(host, true, ui) }); 
-							ui.with_layout(Layout::top_down(Align::TOP), |ui| { self.render_new(host, false, ui) }); 		
-						}
-					};
-					ui.end_row();
+	/// Throws away the current diff (and any that's still being worked out), so that it's redone with the new options.
+	fn reprocess(&mut self) {
+		self.processed = false;
+		self.pending = None;
+		self.selected = None;
+	}
+	
+	/// Parses the include and exclude fields into the diff's scope, leaving it alone if either of them is invalid.
+	fn apply_scope(&mut self) {
+		match (parse_list(&self.include), parse_list(&self.exclude)) {
+			(Ok(include), Ok(exclude)) => {
+				self.err_msg = None;
+				self.options.scope = Scope { include, exclude };
+				self.reprocess();
+			},
+			(Err(e), _) | (_, Err(e)) => self.err_msg = Some(format!("Invalid network: {}", e))
+		}
+	}
+	
+	fn render_history(&mut self, ui: &mut egui::Ui) {
+		ui.horizontal(|ui| {
+			ui.label("Store:");
+			ui.add(egui::TextEdit::singleline(&mut self.history_path).desired_width(ui.available_width() / 3.0));
+			if ui.button("Browse...").clicked() && let Some(path) = FileDialog::new().save_file() {
+				self.history_path = path.display().to_string();
+			}
+			if ui.button("Record these scans").clicked() {
+				let result = HistoryStore::open(&self.history_path).and_then(|mut store| {
+					store.add_scan(self.left_scan.as_ref().unwrap(), &self.left_path)?;
+					store.add_scan(self.right_scan.as_ref().unwrap(), &self.right_path)?;
+					store.add_deltas(self.left_scan.as_ref().unwrap(), self.right_scan.as_ref().unwrap(), &self.deltas)?;
+					store.save()
+				});
+				self.err_msg = result.err().map(|e| format!("Failed to update the history store: {}", e));
+			}
+		});
+		
+		ui.horizontal(|ui| {
+			ui.label("Host:");
+			ui.add(egui::TextEdit::singleline(&mut self.history_host).hint_text("address or hostname, or blank for every open port"));
+			if ui.button("Show").clicked() {
+				// With no host, list when every open port in the store was first and last seen instead.
+				let result = HistoryStore::open_read_only(&self.history_path).and_then(|store| match self.history_host.trim() {
+					"" => store.sightings(None, None, "open").map(|x| x.iter().map(|x| x.to_string()).collect()),
+					host => store.host_history(host)
+				});
+				match result {
+					Ok(lines) => { self.err_msg = None; self.history_lines = lines; },
+					Err(e) => self.err_msg = Some(format!("Failed to read the history store: {}", e))
 				}
-			});
+			}
+		});
+		
+		ui.separator();
+		ScrollArea::vertical().show(ui, |ui| {
+			for line in &self.history_lines {
+				ui.label(egui::RichText::new(line).monospace());
+			}
 		});
 	}
 	
-	fn render_changed(&self, diff: &HostDiff, left: bool, ui: &mut egui::Ui) {
-		let max_width : f32 = ui.ctx().content_rect().max.x;
-		ui.set_width(max_width / 2.0);
+	fn render_deltas(&mut self, ui: &mut egui::Ui) {
+		// Every row is a title line, the report lines beneath it and a separator.
+		let line_height : f32 = ui.text_style_height(&egui::TextStyle::Body);
+		let spacing : f32 = ui.spacing().item_spacing.y;
+		let heights = self.rows.iter().map(|row| { (row.lines() + 1) as f32 * (line_height + spacing) + SEPARATOR_SPACING });
 	
-		let report_color = match left {
-			true => egui::Color32::from_rgb(0x0, 0x80, 0x0),
-			false => egui::Color32::from_rgb(0x80, 0x0, 0x0)
-		};
+		let selected = self.selected;
+		let mut clicked : Option<usize> = None;
+		let palette = Palette::new(self.scheme, ui.visuals().dark_mode);
 	
-		ui.add(Separator::default().spacing(16.0));
-		ui.label(egui::RichText::new(format!("{}", diff.title)).underline().color(report_color));
-		
-		let mut report : String = String::new();
+		TableBuilder::new(ui)
+			.sense(egui::Sense::click())
+			.column(Column::remainder().clip(true))
+			.column(Column::remainder().clip(true))
+			.body(|body| {
+				body.heterogeneous_rows(heights, |mut table_row| {
+					let index = table_row.index();
+					let row = &self.rows[index];
+					table_row.set_selected(selected == Some(index));
+					table_row.col(|ui| { render_side(&row.title, row.meaning, &row.left, &palette, ui) });
+					table_row.col(|ui| { render_side(&row.title, row.meaning, &row.right, &palette, ui) });
+					if table_row.response().clicked() { clicked = Some(index); }
+				});
+			});
 		
-		if let Some(status) = &diff.status {
-			let current_side = match left { true => status.0.clone(), false => status.1.clone() };
-			let status_str = format!("| Status: {} ({})\n", current_side.state.to_string(), current_side.reason);
-			report.push_str(&status_str);
-		}
+		if clicked.is_some() { self.selected = clicked; }
+	}
+	
+	fn render_table(&mut self, ui: &mut egui::Ui) {
+		let row_height : f32 = ui.text_style_height(&egui::TextStyle::Body) + ui.spacing().item_spacing.y;
+		let mut clicked : Option<SortColumn> = None;
+		let selected = self.selected;
+		let mut clicked_row : Option<usize> = None;
 		
-		if let Some(ports) = &diff.ports {
-			let current_side = match left { true => ports.0.clone(), false => ports.1.clone() };
-			let wrapped_ports = PortsWrapper(current_side);
-			let ports_str = format!("| Ports: {}\n", wrapped_ports.to_string());
-			report.push_str(&ports_str);
-		}
+		TableBuilder::new(ui)
+			.striped(true)
+			.sense(egui::Sense::click())
+			.column(Column::auto().at_least(120.0).clip(true))
+			.column(Column::auto().at_least(120.0).clip(true))
+			.column(Column::auto().at_least(80.0))
+			.column(Column::auto().at_least(100.0).clip(true))
+			.column(Column::auto().at_least(100.0).clip(true))
+			.column(Column::remainder().clip(true))
+			.header(row_height + 4.0, |mut header| {
+				for column in SortColumn::ALL {
+					header.col(|ui| {
+						let mut label = column.to_string();
+						if column == self.sort_column {
+							label.push_str(match self.sort_ascending { true => " ⏶", false => " ⏷" });
+						}
+						if ui.button(label).clicked() {
+							clicked = Some(column);
+						}
+					});
+				}
+			})
+			.body(|body| {
+				body.rows(row_height, self.host_order.len(), |mut table_row| {
+					let index = self.host_order[table_row.index()];
+					let row = &self.host_rows[index];
+					table_row.set_selected(selected == Some(index));
+					table_row.col(|ui| { ui.label(&row.address); });
+					table_row.col(|ui| { ui.label(&row.hostname); });
+					table_row.col(|ui| { ui.label(row.kind.to_string()); });
+					table_row.col(|ui| { ui.label(&row.opened); });
+					table_row.col(|ui| { ui.label(&row.closed); });
+					table_row.col(|ui| { ui.label(&row.status); });
+					if table_row.response().clicked() { clicked_row = Some(index); }
+				});
+			});
 		
-		if let Some(addresses) = &diff.addresses {
-			let current_side = match left { true => addresses.0.clone(), false => addresses.1.clone() };
-			let wrapped_addresses = AddressesWrapper(current_side);
-			let addresses_str = format!("| Addresses: {}\n", wrapped_addresses.to_string());
-			report.push_str(&addresses_str);
-		}
+		if clicked_row.is_some() { self.selected = clicked_row; }
 		
-		if let Some(hostnames) = &diff.hostnames {
-			let current_side = match left { true => hostnames.0.clone(), false => hostnames.1.clone() };
-			let wrapped_hostnames = HostnamesWrapper(current_side);
-			let hostnames_str = format!("| Hostnames: {}\n", wrapped_hostnames.to_string());
-			report.push_str(&hostnames_str);
+		// Clicking the current sort column flips the direction, clicking any other column sorts by it.
+		if let Some(column) = clicked {
+			match column == self.sort_column {
+				true => self.sort_ascending = !self.sort_ascending,
+				false => { self.sort_column = column; self.sort_ascending = true; }
+			};
+			self.sort_hosts();
 		}
-		
-		ui.label(egui::RichText::new(report).color(report_color));
 	}
 	
-	fn render_unchanged(&self, host: &Host, ui: &mut egui::Ui) {
-		let max_width : f32 = ui.ctx().content_rect().max.x;
-		ui.set_width(max_width / 2.0);
-	
-		let report_color = egui::Color32::from_rgb(0x0, 0x80, 0x0);
+	fn render_details(&self, index: usize, ui: &mut egui::Ui) {
+		let delta = &self.deltas[index];
+		let (left_host, right_host) = delta.sides();
+		let left_details = match (left_host, &self.left_scan) { (Some(host), Some(scan)) => scan.details.host(host), _ => None };
+		let right_details = match (right_host, &self.right_scan) { (Some(host), Some(scan)) => scan.details.host(host), _ => None };
+		let palette = Palette::new(self.scheme, ui.visuals().dark_mode);
 		
-		let wrapped_host = HostWrapper(host.clone());
+		ui.label(egui::RichText::new(self.rows[index].title.clone()).underline());
+		ui.label(format!("Delta: {}", delta.kind()));
+		ui.add_space(8.0);
 		
-		ui.add(Separator::default().spacing(16.0));
-		ui.label(egui::RichText::new(format!("{}", wrapped_host.get_title())).underline().color(report_color));
+		ui.strong("Ports");
+		Grid::new("detail_ports").striped(true).show(ui, |ui| {
+			for heading in ["", "Protocol", "Port", "Left", "Right", "Service", "Product/Version"] {
+				ui.strong(heading);
+			}
+			ui.end_row();
+			
+			for row in PortDetailRow::from_hosts((left_host, left_details), (right_host, right_details)) {
+				let cells = [row.meaning.marker().to_string(), row.protocol, row.port, row.left_state, row.right_state, row.service, row.product];
+				for cell in cells {
+					ui.label(egui::RichText::new(cell).color(palette.color(row.meaning)));
+				}
+				ui.end_row();
+			}
+		});
+		ui.add_space(8.0);
 		
-		let mut report : String = String::new();
-		report.push_str("(NO CHANGE)");
+		let side_by_side = |ui: &mut egui::Ui, id: &str, heading: &str, left: Vec<String>, right: Vec<String>| {
+			let meaning = match left == right { true => Meaning::Unchanged, false => Meaning::Changed };
+			ui.strong(format!("{} {}", meaning.marker(), heading));
+			Grid::new(id).num_columns(2).show(ui, |ui| {
+				for side in [left, right] {
+					let text = match side.is_empty() { true => "<nothing to show>".to_string(), false => side.join("\n") };
+					ui.label(egui::RichText::new(text).color(palette.color(meaning)));
+				}
+				ui.end_row();
+			});
+			ui.add_space(8.0);
+		};
 		
-		ui.label(egui::RichText::new(report).color(report_color));
+		let addresses = |host: Option<&Host>| match host { Some(x) => AddressesWrapper(x.addresses().cloned().collect()).entries(), None => Vec::new() };
+		let hostnames = |host: Option<&Host>| match host { Some(x) => HostnamesWrapper(x.host_names().cloned().collect()).entries(), None => Vec::new() };
+		let os_guesses = |details: Option<&HostDetails>| match details {
+			Some(x) => x.os_matches.iter().map(|x| format!("{} ({}%)", x.name, x.accuracy)).collect(),
+			None => Vec::new()
+		};
+		let scripts = |details: Option<&HostDetails>| match details {
+			Some(x) => {
+				let mut output : Vec<String> = x.scripts.iter().map(|x| format!("{}: {}", x.id, x.output.trim())).collect();
+				for service in &x.services {
+					for script in &service.scripts {
+						output.push(format!("{}/{} {}: {}", service.protocol, service.port_number, script.id, script.output.trim()));
+					}
+				}
+				output
+			},
+			None => Vec::new()
+		};
+		
+		side_by_side(ui, "detail_addresses", "Addresses", addresses(left_host), addresses(right_host));
+		side_by_side(ui, "detail_hostnames", "Hostnames", hostnames(left_host), hostnames(right_host));
+		side_by_side(ui, "detail_os", "OS Guesses", os_guesses(left_details), os_guesses(right_details));
+		side_by_side(ui, "detail_scripts", "Script Output", scripts(left_details), scripts(right_details));
 	}
 	
-	fn render_gone(&self, host: &Host, left: bool, ui: &mut egui::Ui) {
-		let max_width : f32 = ui.ctx().content_rect().max.x;
-		ui.set_width(max_width / 2.0);
-	
-		let report_color = match left {
-			true => egui::Color32::from_rgb(0x0, 0x80, 0x0),
-			false => egui::Color32::from_rgb(0x80, 0x0, 0x0)
+	fn sort_hosts(&mut self) {
+		self.host_order = sort_order(&self.host_rows, self.sort_column, self.sort_ascending);
+	}
+}
+
+/// Returns the indices of the rows in the order they should be shown in the table view.
+fn sort_order(rows: &[HostRow], column: SortColumn, ascending: bool) -> Vec<usize> {
+	let mut order : Vec<usize> = (0..rows.len()).collect();
+	order.sort_by(|a, b| {
+		let ordering = rows[*a].compare(&rows[*b], column);
+		match ascending { true => ordering, false => ordering.reverse() }
+	});
+	order
+}
+
+/// The diff of a pair of scans along with everything that's precomputed for displaying it, which is worked out on a
+/// background thread.
+struct Processed {
+	deltas: Vec<HostDelta>,
+	rows: Vec<DeltaRow>,
+	host_rows: Vec<HostRow>,
+	host_order: Vec<usize>,
+	comparison: ScanComparison
+}
+
+impl Processed {
+	fn from_scans(left: &Scan, right: &Scan, options: &DiffOptions, sort_column: SortColumn, sort_ascending: bool) -> Processed {
+		let deltas = HostDelta::from_scans(left, right, options);
+		let rows = deltas.iter().map(DeltaRow::from_delta).collect();
+		let host_rows : Vec<HostRow> = deltas.iter().map(HostRow::from_delta).collect();
+		let host_order = sort_order(&host_rows, sort_column, sort_ascending);
+		Processed { deltas, rows, host_rows, host_order, comparison: ScanComparison::from_scans(left, right) }
+	}
+}
+
+#[derive(Clone,Copy,PartialEq)]
+enum View {
+	SideBySide,
+	Table,
+	History
+}
+
+#[derive(Clone,Copy,PartialEq)]
+enum SortColumn {
+	Address,
+	Hostname,
+	Kind,
+	Opened,
+	Closed,
+	Status
+}
+
+impl SortColumn {
+	const ALL : [SortColumn; 6] = [SortColumn::Address, SortColumn::Hostname, SortColumn::Kind, SortColumn::Opened, SortColumn::Closed, SortColumn::Status];
+}
+
+impl fmt::Display for SortColumn {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let display_str = match self {
+			SortColumn::Address => "Address",
+			SortColumn::Hostname => "Hostname",
+			SortColumn::Kind => "Delta",
+			SortColumn::Opened => "Ports Opened",
+			SortColumn::Closed => "Ports Closed",
+			SortColumn::Status => "Status"
 		};
+		write!(f, "{}", display_str)
+	}
+}
+
+/// Precomputed cells for a single host in the table view.
+struct HostRow {
+	address: String,
+	ip: Option<IpAddr>,
+	hostname: String,
+	kind: DeltaKind,
+	opened: String,
+	opened_count: usize,
+	closed: String,
+	closed_count: usize,
+	status: String
+}
+
+impl HostRow {
+	fn from_delta(delta: &HostDelta) -> HostRow {
+		let host = delta.host();
 		
-		let wrapped_host = HostWrapper(host.clone());
+		// Prefer an IP address for the address column, falling back to whatever address the host has.
+		let ip = host.addresses().find_map(|x| match x { Address::IpAddr(ip) => Some(*ip), Address::MacAddr(_) => None });
+		let address = match ip {
+			Some(x) => x.to_string(),
+			None => match host.addresses().next() { Some(x) => AddressesWrapper(vec![x.clone()]).entries().join(""), None => "<no address>".to_string() }
+		};
+		let hostname = match host.host_names().next() { Some(x) => x.name.to_string(), None => "<no hostname>".to_string() };
 		
-		ui.add(Separator::default().spacing(16.0));
-		ui.label(egui::RichText::new(format!("{}", wrapped_host.get_title())).underline().color(report_color));
+		let opened = delta.opened_ports();
+		let closed = delta.closed_ports();
 		
-		let mut report : String = String::new();
+		let status = match delta {
+			HostDelta::Changed(diff) => match &diff.status {
+				Some(status) => format!("{} => {}", status.0.state, status.1.state),
+				None => host.status.state.to_string()
+			},
+			_ => host.status.state.to_string()
+		};
 		
-		match left {
-			true => report.push_str(&wrapped_host.to_string()),
-			false => report.push_str("(HOST GONE)")
+		HostRow {
+			address,
+			ip,
+			hostname,
+			kind: delta.kind(),
+			opened: port_list(&opened),
+			opened_count: opened.len(),
+			closed: port_list(&closed),
+			closed_count: closed.len(),
+			status
 		}
-		
-		ui.label(egui::RichText::new(report).color(report_color));
 	}
 	
-	fn render_new(&self, host: &Host, left: bool, ui: &mut egui::Ui) {
-		let max_width : f32 = ui.ctx().content_rect().max.x;
-		ui.set_width(max_width / 2.0);
-	
-		let report_color = match left {
-			true => egui::Color32::from_rgb(0x80, 0x0, 0x0),
-			false => egui::Color32::from_rgb(0x0, 0x80, 0x0)
-		};
+	fn compare(&self, other: &HostRow, column: SortColumn) -> Ordering {
+		match column {
+			// IP addresses sort numerically (IPv4 before IPv6), followed by hosts that only have a MAC address.
+			SortColumn::Address => (self.ip.is_none(), self.ip, &self.address).cmp(&(other.ip.is_none(), other.ip, &other.address)),
+			SortColumn::Hostname => self.hostname.cmp(&other.hostname),
+			SortColumn::Kind => self.kind.cmp(&other.kind),
+			SortColumn::Opened => self.opened_count.cmp(&other.opened_count),
+			SortColumn::Closed => self.closed_count.cmp(&other.closed_count),
+			SortColumn::Status => self.status.cmp(&other.status)
+		}
+	}
+}
+
+/// A single row of the port table in the host details panel, comparing one port across both scans.
+struct PortDetailRow {
+	protocol: String,
+	port: String,
+	left_state: String,
+	right_state: String,
+	service: String,
+	product: String,
+	meaning: Meaning
+}
+
+impl PortDetailRow {
+	fn from_hosts(left: (Option<&Host>, Option<&HostDetails>), right: (Option<&Host>, Option<&HostDetails>)) -> Vec<PortDetailRow> {
+		let left_ports : Vec<Port> = match left.0 { Some(x) => x.port_info.ports().cloned().collect(), None => Vec::new() };
+		let right_ports : Vec<Port> = match right.0 { Some(x) => x.port_info.ports().cloned().collect(), None => Vec::new() };
 		
-		let wrapped_host = HostWrapper(host.clone());
+		// Every port that appears on either side, in protocol and port order.
+		let mut keys : Vec<(String, u16, PortProtocol)> = Vec::new();
+		for port in left_ports.iter().chain(right_ports.iter()) {
+			let key = (port.protocol.to_string(), port.port_number, port.protocol.clone());
+			if !keys.contains(&key) { keys.push(key); }
+		}
+		keys.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+		
+		keys.into_iter().map(|(protocol, port_number, port_protocol)| {
+			let find = |ports: &[Port]| ports.iter().find(|x| x.protocol == port_protocol && x.port_number == port_number).cloned();
+			let left_port = find(&left_ports);
+			let right_port = find(&right_ports);
+			let left_service = left.1.and_then(|x| x.service(&port_protocol, port_number)).map(|x| x.product_version()).unwrap_or_default();
+			let right_service = right.1.and_then(|x| x.service(&port_protocol, port_number)).map(|x| x.product_version()).unwrap_or_default();
+			
+			let state = |port: &Option<Port>| match port { Some(x) => x.status.state.to_string(), None => "-".to_string() };
+			let service = |port: &Option<Port>| port.as_ref().and_then(|x| x.service_info.as_ref()).map(|x| x.name.clone()).unwrap_or_default();
+			
+			PortDetailRow {
+				protocol,
+				port: port_number.to_string(),
+				left_state: state(&left_port),
+				right_state: state(&right_port),
+				service: either_side(service(&left_port), service(&right_port)),
+				product: either_side(left_service.clone(), right_service.clone()),
+				meaning: match (&left_port, &right_port) {
+					(None, Some(_)) => Meaning::Added,
+					(Some(_), None) => Meaning::Removed,
+					_ if left_port != right_port || left_service != right_service => Meaning::Changed,
+					_ => Meaning::Unchanged
+				}
+			}
+		}).collect()
+	}
+}
+
+/// Shows a single value if both sides agree, otherwise "left => right".
+fn either_side(left: String, right: String) -> String {
+	match left == right || left.is_empty() || right.is_empty() {
+		true => match left.is_empty() { true => right, false => left },
+		false => format!("{} => {}", left, right)
+	}
+}
+
+fn port_list(ports: &[Port]) -> String {
+	let entries : Vec<String> = ports.iter().map(|x| format!("{}/{}", x.protocol, x.port_number)).collect();
+	entries.join(", ")
+}
+
+/// The vertical space taken up by the separator at the top of each row.
+const SEPARATOR_SPACING : f32 = 16.0;
+
+/// Precomputed text for a single row of the delta view, so that only the rows that are visible need to be laid out each frame.
+struct DeltaRow {
+	title: String,
+	meaning: Meaning,
+	left: Vec<ReportLine>,
+	right: Vec<ReportLine>
+}
+
+/// A single line of a report in the delta view, along with what it represents.
+struct ReportLine {
+	text: String,
+	meaning: Meaning
+}
+
+impl DeltaRow {
+	fn from_delta(delta: &HostDelta) -> DeltaRow {
+		let mut builder = RowBuilder::default();
 		
-		ui.add(Separator::default().spacing(16.0));
-		ui.label(egui::RichText::new(format!("{}", wrapped_host.get_title())).underline().color(report_color));
+		let (title, meaning) = match delta {
+			HostDelta::Changed(diff) => {
+				if let Some(status) = &diff.status {
+					builder.line(Some((status_line(&status.0), Meaning::Changed)), Some((status_line(&status.1), Meaning::Changed)));
+				}
+				if diff.ports.is_some() {
+					builder.heading("Ports");
+					for change in diff.port_changes() { builder.change(&change, |x| PortWrapper(x.clone()).to_string()); }
+				}
+				if diff.addresses.is_some() {
+					builder.heading("Addresses");
+					for change in diff.address_changes() { builder.change(&change, |x| AddressWrapper(x.clone()).to_string()); }
+				}
+				if diff.hostnames.is_some() {
+					builder.heading("Hostnames");
+					for change in diff.hostname_changes() { builder.change(&change, |x| x.name.clone()); }
+				}
+				if let Some(os) = &diff.os {
+					builder.line(Some((format!("| OS: {}", os.0), Meaning::Changed)), Some((format!("| OS: {}", os.1), Meaning::Changed)));
+				}
+				if let Some(reboot) = &diff.reboot {
+					builder.line(Some((format!("| Last boot: {}", format_timestamp(reboot.0)), Meaning::Changed)), Some((format!("| Rebooted: {}", format_timestamp(reboot.1)), Meaning::Changed)));
+				}
+				if let Some(route) = &diff.route {
+					builder.line(Some((format!("| Route: {}", route.0), Meaning::Changed)), Some((format!("| Route: {}", route.1), Meaning::Changed)));
+				}
+				(diff.title.clone(), Meaning::Changed)
+			},
+			HostDelta::Unchanged(host, notes) => {
+				// There's nothing to compare, so the host is only shown once.
+				builder.left.push(ReportLine { text: "(NO CHANGE)".to_string(), meaning: Meaning::Unchanged });
+				for note in notes {
+					let label = match note {
+						Change::Collapsed(_) => "Collapsed into extraports",
+						_ => "Expanded from extraports"
+					};
+					let port = note.latest();
+					builder.left.push(ReportLine { text: format!("| {}: {} {} ({})", label, port.protocol, port.port_number, port.status.state), meaning: Meaning::Unchanged });
+				}
+				(HostWrapper(host.clone()).get_title(), Meaning::Unchanged)
+			},
+			HostDelta::Gone(host) => {
+				builder.left = host_lines(host, Meaning::Removed);
+				builder.right.push(ReportLine { text: "(HOST GONE)".to_string(), meaning: Meaning::Removed });
+				(HostWrapper(host.clone()).get_title(), Meaning::Removed)
+			},
+			HostDelta::New(host) => {
+				builder.left.push(ReportLine { text: "(NEW HOST)".to_string(), meaning: Meaning::Added });
+				builder.right = host_lines(host, Meaning::Added);
+				(HostWrapper(host.clone()).get_title(), Meaning::Added)
+			}
+		};
 		
-		let mut report : String = String::new();
+		DeltaRow { title, meaning, left: builder.left, right: builder.right }
+	}
+	
+	fn lines(&self) -> usize {
+		self.left.len().max(self.right.len())
+	}
+}
+
+/// Builds the two sides of a row line by line, so that each element lines up with its counterpart on the other side.
+#[derive(Default)]
+struct RowBuilder {
+	left: Vec<ReportLine>,
+	right: Vec<ReportLine>
+}
+
+impl RowBuilder {
+	fn line(&mut self, left: Option<(String, Meaning)>, right: Option<(String, Meaning)>) {
+		for (side, line) in [(&mut self.left, left), (&mut self.right, right)] {
+			let (text, meaning) = line.unwrap_or((String::new(), Meaning::Unchanged));
+			side.push(ReportLine { text, meaning });
+		}
+	}
+	
+	fn heading(&mut self, label: &str) {
+		let text = format!("| {}:", label);
+		self.line(Some((text.clone(), Meaning::Unchanged)), Some((text, Meaning::Unchanged)));
+	}
+	
+	fn change<T>(&mut self, change: &Change<T>, describe: impl Fn(&T) -> String) {
+		match change {
+			Change::Same(x) => self.line(Some((entry_line(&describe(x), Meaning::Unchanged), Meaning::Unchanged)), Some((entry_line(&describe(x), Meaning::Unchanged), Meaning::Unchanged))),
+			Change::Added(x) => self.line(None, Some((entry_line(&describe(x), Meaning::Added), Meaning::Added))),
+			Change::Removed(x) => self.line(Some((entry_line(&describe(x), Meaning::Removed), Meaning::Removed)), None),
+			Change::Changed(a, b) => self.line(Some((entry_line(&describe(a), Meaning::Changed), Meaning::Changed)), Some((entry_line(&describe(b), Meaning::Changed), Meaning::Changed))),
+			Change::Collapsed(x) => self.line(Some((entry_line(&describe(x), Meaning::Unchanged), Meaning::Unchanged)), Some((entry_line("(collapsed into extraports)", Meaning::Unchanged), Meaning::Unchanged))),
+			Change::Expanded(x) => self.line(Some((entry_line("(hidden in extraports)", Meaning::Unchanged), Meaning::Unchanged)), Some((entry_line(&describe(x), Meaning::Unchanged), Meaning::Unchanged))),
+			Change::OnlyScannedLeft(x) => self.line(Some((entry_line(&describe(x), Meaning::Changed), Meaning::Changed)), Some((entry_line("(not scanned)", Meaning::Changed), Meaning::Changed))),
+			Change::OnlyScannedRight(x) => self.line(Some((entry_line("(not scanned)", Meaning::Changed), Meaning::Changed)), Some((entry_line(&describe(x), Meaning::Changed), Meaning::Changed)))
+		}
+	}
+}
+
+fn render_side(title: &str, meaning: Meaning, lines: &[ReportLine], palette: &Palette, ui: &mut egui::Ui) {
+	ui.with_layout(Layout::top_down(Align::TOP), |ui| {
+		ui.add(Separator::default().spacing(SEPARATOR_SPACING));
+		if lines.is_empty() { return; }
 		
-		match left {
-			true => report.push_str("(NEW HOST)"),
-			false => report.push_str(&wrapped_host.to_string())
+		ui.add(egui::Label::new(egui::RichText::new(format!("{} {}", meaning.marker(), title)).underline().color(palette.color(meaning))).truncate());
+		for line in lines {
+			ui.add(egui::Label::new(egui::RichText::new(&line.text).color(palette.color(line.meaning))).extend());
+		}
+	});
+}
+
+/// Formats a single entry within a section, e.g. a port, with a marker showing how it changed.
+fn entry_line(entry: &str, meaning: Meaning) -> String {
+	format!("|   {} {}", meaning.marker(), entry)
+}
+
+fn status_line(status: &HostStatus) -> String {
+	format!("| Status: {} ({})", status.state, status.reason)
+}
+
+/// Lists everything about a host, with one entry per line so that the height of each row is known in advance.
+fn host_lines(host: &Host, meaning: Meaning) -> Vec<ReportLine> {
+	let mut lines : Vec<ReportLine> = vec![ReportLine { text: status_line(&host.status), meaning }];
+	
+	let sections = [
+		("Ports", PortsWrapper(host.port_info.ports().cloned().collect()).entries()),
+		("Addresses", AddressesWrapper(host.addresses().cloned().collect()).entries()),
+		("Hostnames", HostnamesWrapper(host.host_names().cloned().collect()).entries())
+	];
+	
+	for (label, entries) in sections {
+		if entries.is_empty() {
+			lines.push(ReportLine { text: format!("| {}: <nothing to show>", label), meaning });
+			continue;
 		}
 		
-		ui.label(egui::RichText::new(report).color(report_color));
+		lines.push(ReportLine { text: format!("| {}:", label), meaning });
+		for entry in entries {
+			lines.push(ReportLine { text: entry_line(&entry, meaning), meaning });
+		}
 	}
+	
+	lines
 }
 
 fn get_time(scan : &NmapResults) -> String {
@@ -0,0 +1,367 @@
+use std::fmt;
+use std::path::Path;
+
+use nmap_xml_parser::host::Host;
+use rusqlite::Connection;
+use rusqlite::OpenFlags;
+use rusqlite::OptionalExtension;
+use rusqlite::params;
+
+use crate::host::AddressesWrapper;
+use crate::host::HostDelta;
+use crate::host::format_timestamp;
+use crate::scan::Scan;
+use crate::severity::port_event;
+
+const SCHEMA : &str = "
+	CREATE TABLE IF NOT EXISTS scans (
+		id INTEGER PRIMARY KEY,
+		time INTEGER NOT NULL,
+		targets TEXT NOT NULL,
+		source TEXT NOT NULL,
+		UNIQUE (time, targets)
+	);
+	CREATE TABLE IF NOT EXISTS hosts (
+		scan INTEGER NOT NULL REFERENCES scans (id),
+		addresses TEXT NOT NULL,
+		hostname TEXT NOT NULL,
+		state TEXT NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS host_addresses (
+		scan INTEGER NOT NULL REFERENCES scans (id),
+		host TEXT NOT NULL,
+		address TEXT NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS ports (
+		scan INTEGER NOT NULL REFERENCES scans (id),
+		addresses TEXT NOT NULL,
+		hostname TEXT NOT NULL,
+		protocol TEXT NOT NULL,
+		port INTEGER NOT NULL,
+		state TEXT NOT NULL,
+		service TEXT NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS changes (
+		left_scan INTEGER NOT NULL REFERENCES scans (id),
+		right_scan INTEGER NOT NULL REFERENCES scans (id),
+		addresses TEXT NOT NULL,
+		hostname TEXT NOT NULL,
+		description TEXT NOT NULL
+	);
+	CREATE INDEX IF NOT EXISTS ports_by_state ON ports (state, port);
+";
+
+/// Matches a host record against a query, which is either one of its addresses or its hostname.
+const MATCHES_HOST : &str = "(hostname = ?1 COLLATE NOCASE OR instr(',' || lower(addresses) || ',', ',' || lower(?1) || ',') > 0)";
+
+/// A local SQLite database of scans and the diffs between them, so that questions like "when did port 8080 first open on this
+/// host?" can be answered across months of scans. Everything added to the store is written out in a single transaction when
+/// it's saved. Stores opened read-only can only be queried.
+pub struct HistoryStore {
+	connection: Connection
+}
+
+/// The first and last time a port was seen in a particular state on one IP address, out of every scan in the store. Ports are
+/// tracked by IP address rather than by host, so that a host which gains or loses an address keeps its history.
+#[derive(Debug,Clone)]
+pub struct PortSighting {
+	pub address: String,
+	pub hostname: String,
+	pub protocol: String,
+	pub port: u16,
+	pub first_seen: i64,
+	pub last_seen: i64,
+	pub scans: usize
+}
+
+impl HistoryStore {
+	/// Opens a store to record scans in, creating it if the file doesn't exist yet.
+	pub fn open(path : &str) -> Result<HistoryStore,String> {
+		let connection = Connection::open(path).map_err(|e| format!("couldn't open '{}': {}", path, e))?;
+		connection.execute_batch(SCHEMA).map_err(|e| format!("couldn't set up '{}': {}", path, e))?;
+		connection.execute_batch("BEGIN").map_err(|e| e.to_string())?;
+		Ok(HistoryStore { connection })
+	}
+	
+	/// Opens an existing store for querying. Unlike `open`, this fails if the file doesn't exist, so that a mistyped path isn't
+	/// mistaken for an empty store.
+	pub fn open_read_only(path : &str) -> Result<HistoryStore,String> {
+		if !Path::new(path).is_file() { return Err(format!("'{}' doesn't exist", path)); }
+		let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(|e| format!("couldn't open '{}': {}", path, e))?;
+		Ok(HistoryStore { connection })
+	}
+	
+	/// Adds every host and port from a scan, unless the same scan is already in the store. Scans are identified by their start
+	/// time and targets, since scanners running in parallel can start scans of different targets in the same second.
+	pub fn add_scan(&mut self, scan : &Scan, source : &str) -> Result<(),String> {
+		let time = scan.results.scan_start_time;
+		let targets = scan.targets().join(" ");
+		
+		let inserted = self.connection.execute("INSERT OR IGNORE INTO scans (time, targets, source) VALUES (?1, ?2, ?3)", params![time, targets, source])
+			.map_err(|e| e.to_string())?;
+		if inserted == 0 { return Ok(()); }
+		let id = self.connection.last_insert_rowid();
+		
+		for host in scan.results.hosts() {
+			let (addresses, hostname) = host_key(host);
+			self.connection.execute("INSERT INTO hosts (scan, addresses, hostname, state) VALUES (?1, ?2, ?3, ?4)", params![id, addresses, hostname, host.status.state.to_string()])
+				.map_err(|e| e.to_string())?;
+			for address in tracked_addresses(host) {
+				self.connection.execute("INSERT INTO host_addresses (scan, host, address) VALUES (?1, ?2, ?3)", params![id, addresses, address])
+					.map_err(|e| e.to_string())?;
+			}
+			for port in host.port_info.ports() {
+				let service = port.service_info.as_ref().map(|x| x.name.clone()).unwrap_or_default();
+				self.connection.execute(
+					"INSERT INTO ports (scan, addresses, hostname, protocol, port, state, service) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+					params![id, addresses, hostname, port.protocol.to_string(), port.port_number, port.status.state.to_string(), service]
+				).map_err(|e| e.to_string())?;
+			}
+		}
+		
+		Ok(())
+	}
+	
+	/// Adds the changes between two scans, unless changes between the same two scans are already in the store. Both scans have
+	/// to have been added first.
+	pub fn add_deltas(&mut self, left : &Scan, right : &Scan, deltas : &[HostDelta]) -> Result<(),String> {
+		let (left_id, right_id) = match (self.scan_id(left)?, self.scan_id(right)?) {
+			(Some(left_id), Some(right_id)) => (left_id, right_id),
+			_ => return Err("both scans have to be in the store before their changes".to_string())
+		};
+		let existing : i64 = self.connection.query_row("SELECT COUNT(*) FROM changes WHERE left_scan = ?1 AND right_scan = ?2", params![left_id, right_id], |row| row.get(0))
+			.map_err(|e| e.to_string())?;
+		if existing > 0 { return Ok(()); }
+		
+		for delta in deltas {
+			let (addresses, hostname) = host_key(delta.host());
+			let mut descriptions : Vec<String> = match delta {
+				HostDelta::New(_) => vec!["host appeared".to_string()],
+				HostDelta::Gone(_) => vec!["host disappeared".to_string()],
+				HostDelta::Changed(diff) => diff.status.iter().map(|x| format!("status {} => {}", x.0.state, x.1.state)).collect(),
+				HostDelta::Unchanged(_, _) => continue
+			};
+			descriptions.extend(delta.port_changes().iter().filter_map(|change| {
+				let (port, event) = port_event(change)?;
+				Some(format!("{} {} {}", port.protocol, port.port_number, event))
+			}));
+			
+			for description in descriptions {
+				self.connection.execute(
+					"INSERT INTO changes (left_scan, right_scan, addresses, hostname, description) VALUES (?1, ?2, ?3, ?4, ?5)",
+					params![left_id, right_id, addresses, hostname, description]
+				).map_err(|e| e.to_string())?;
+			}
+		}
+		
+		Ok(())
+	}
+	
+	/// Commits everything added since the store was opened.
+	pub fn save(&mut self) -> Result<(),String> {
+		self.connection.execute_batch("COMMIT; BEGIN").map_err(|e| format!("couldn't save the store: {}", e))
+	}
+	
+	fn scan_id(&self, scan : &Scan) -> Result<Option<i64>,String> {
+		self.connection.query_row("SELECT id FROM scans WHERE time = ?1 AND targets = ?2", params![scan.results.scan_start_time, scan.targets().join(" ")], |row| row.get(0))
+			.optional()
+			.map_err(|e| e.to_string())
+	}
+	
+	/// Describes what happened to a host (given by address or hostname) in every scan in the store, oldest first.
+	pub fn host_history(&self, host : &str) -> Result<Vec<String>,String> {
+		let mut output : Vec<String> = Vec::new();
+		
+		let mut scans = self.connection.prepare("SELECT id, time FROM scans ORDER BY time, id").map_err(|e| e.to_string())?;
+		let scans : Vec<(i64, i64)> = scans.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+			.and_then(|rows| rows.collect())
+			.map_err(|e| e.to_string())?;
+		
+		for (id, time) in scans {
+			let record : Option<(String, String)> = self.connection.query_row(
+				&format!("SELECT addresses, state FROM hosts WHERE scan = ?2 AND {} LIMIT 1", MATCHES_HOST), params![host, id], |row| Ok((row.get(0)?, row.get(1)?))
+			).optional().map_err(|e| e.to_string())?;
+			
+			let summary = match record {
+				Some((addresses, state)) => {
+					let mut ports = self.connection.prepare("SELECT protocol, port, service FROM ports WHERE scan = ?1 AND addresses = ?2 AND state = 'open' ORDER BY protocol, port")
+						.map_err(|e| e.to_string())?;
+					let open : Vec<String> = ports.query_map(params![id, addresses], |row| {
+						let (protocol, port, service) : (String, u16, String) = (row.get(0)?, row.get(1)?, row.get(2)?);
+						Ok(match service.is_empty() { true => format!("{} {}", protocol, port), false => format!("{} {} ({})", protocol, port, service) })
+					}).and_then(|rows| rows.collect()).map_err(|e| e.to_string())?;
+					match open.is_empty() {
+						true => format!("{} ({}), no open ports", state, addresses),
+						false => format!("{} ({}), open: {}", state, addresses, open.join(", "))
+					}
+				},
+				None => "not in scan".to_string()
+			};
+			output.push(format!("{} | {}", format_timestamp(time), summary));
+			
+			let mut changes = self.connection.prepare(&format!(
+				"SELECT scans.time, changes.description FROM changes JOIN scans ON scans.id = changes.left_scan WHERE changes.right_scan = ?2 AND {}", MATCHES_HOST
+			)).map_err(|e| e.to_string())?;
+			let changes : Vec<(i64, String)> = changes.query_map(params![host, id], |row| Ok((row.get(0)?, row.get(1)?)))
+				.and_then(|rows| rows.collect())
+				.map_err(|e| e.to_string())?;
+			for (left_time, description) in changes {
+				output.push(format!("{} |   since {}: {}", format_timestamp(time), format_timestamp(left_time), description));
+			}
+		}
+		
+		Ok(output)
+	}
+	
+	/// Finds when ports were first and last seen in the given state on each IP address, optionally limited to one host and port.
+	pub fn sightings(&self, host : Option<&str>, port : Option<u16>, state : &str) -> Result<Vec<PortSighting>,String> {
+		let mut statement = self.connection.prepare(&format!("
+			SELECT host_addresses.address, MAX(hostname), protocol, port, MIN(scans.time), MAX(scans.time), COUNT(*)
+			FROM ports
+				JOIN scans ON scans.id = ports.scan
+				JOIN host_addresses ON host_addresses.scan = ports.scan AND host_addresses.host = ports.addresses
+			WHERE state = ?2 AND (?3 IS NULL OR port = ?3) AND (?1 IS NULL OR {})
+			GROUP BY host_addresses.address, protocol, port
+			ORDER BY MIN(scans.time), host_addresses.address, protocol, port
+		", MATCHES_HOST)).map_err(|e| e.to_string())?;
+		
+		statement.query_map(params![host, state, port], |row| Ok(PortSighting {
+			address: row.get(0)?,
+			hostname: row.get(1)?,
+			protocol: row.get(2)?,
+			port: row.get(3)?,
+			first_seen: row.get(4)?,
+			last_seen: row.get(5)?,
+			scans: row.get(6)?
+		})).and_then(|rows| rows.collect()).map_err(|e| e.to_string())
+	}
+}
+
+/// Returns the addresses and first hostname of a host, which are used to find it again later.
+fn host_key(host : &Host) -> (String, String) {
+	let addresses = AddressesWrapper(host.addresses().cloned().collect()).entries().join(",");
+	let hostname = host.host_names().next().map(|x| x.name.clone()).unwrap_or_default();
+	(addresses, hostname)
+}
+
+/// Returns the addresses that a host's ports are tracked by across scans: its IP addresses, or its MAC address if it has none.
+fn tracked_addresses(host : &Host) -> Vec<String> {
+	let addresses = AddressesWrapper(host.addresses().cloned().collect());
+	match addresses.sorted_ips() {
+		ips if ips.is_empty() => addresses.entries(),
+		ips => ips
+	}
+}
+
+impl fmt::Display for PortSighting {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let host = match self.hostname.is_empty() {
+			true => self.address.clone(),
+			false => format!("{} ({})", self.hostname, self.address)
+		};
+		write!(f, "{} {} {}: first seen {}, last seen {}, in {} scans", host, self.protocol, self.port, format_timestamp(self.first_seen), format_timestamp(self.last_seen), self.scans)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	use crate::host::DiffOptions;
+	
+	fn scan_at(start : i64, targets : &str, hosts : &str) -> Scan {
+		Scan::parse(&nmaprun(&format!("nmap {}", targets), start, hosts)).unwrap()
+	}
+	
+	fn count(store : &HistoryStore, table : &str) -> i64 {
+		store.connection.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+	}
+	
+	#[test]
+	fn adds_each_scan_once() {
+		let mut store = HistoryStore::open(":memory:").unwrap();
+		let scan = scan_at(1700000000, "10.0.0.0/24", &host(&["10.0.0.1"], Some("gw.example"), &open(&[22, 80])));
+		store.add_scan(&scan, "a.xml").unwrap();
+		store.add_scan(&scan, "copy-of-a.xml").unwrap();
+		assert_eq!((count(&store, "scans"), count(&store, "hosts"), count(&store, "ports")), (1, 1, 2));
+		
+		// A scan of other targets that started in the same second is a different scan.
+		store.add_scan(&scan_at(1700000000, "10.0.1.0/24", &host(&["10.0.1.1"], None, &open(&[22]))), "b.xml").unwrap();
+		assert_eq!((count(&store, "scans"), count(&store, "ports")), (2, 3));
+		store.save().unwrap();
+	}
+	
+	#[test]
+	fn adds_changes_once_both_scans_are_in_the_store() {
+		let mut store = HistoryStore::open(":memory:").unwrap();
+		let left = scan_at(1700000000, "10.0.0.0/24", &host(&["10.0.0.1"], None, &open(&[22])));
+		let right = scan_at(1700086400, "10.0.0.0/24", &host(&["10.0.0.1"], None, &open(&[22, 8080])));
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		
+		store.add_scan(&left, "a.xml").unwrap();
+		assert_eq!(store.add_deltas(&left, &right, &deltas).unwrap_err(), "both scans have to be in the store before their changes");
+		store.add_scan(&right, "b.xml").unwrap();
+		store.add_deltas(&left, &right, &deltas).unwrap();
+		store.add_deltas(&left, &right, &deltas).unwrap();
+		assert_eq!(count(&store, "changes"), 1);
+	}
+	
+	#[test]
+	fn describes_a_host_in_every_scan() {
+		let mut store = HistoryStore::open(":memory:").unwrap();
+		let scans = [
+			scan_at(1700000000, "10.0.0.0/24", &host(&["10.0.0.1"], Some("gw.example"), &open(&[22]))),
+			scan_at(1700086400, "10.0.0.0/24", ""),
+			scan_at(1700172800, "10.0.0.0/24", &host(&["10.0.0.1"], Some("gw.example"), &[port(22, "closed"), service(8080, "http-proxy", "Squid", "6.6")].concat()))
+		];
+		for (number, scan) in scans.iter().enumerate() {
+			store.add_scan(scan, &format!("{}.xml", number)).unwrap();
+		}
+		store.add_deltas(&scans[0], &scans[2], &HostDelta::from_scans(&scans[0], &scans[2], &DiffOptions::default())).unwrap();
+		
+		let expected = vec![
+			format!("{} | up (10.0.0.1), open: tcp 22", format_timestamp(1700000000)),
+			format!("{} | not in scan", format_timestamp(1700086400)),
+			format!("{} | up (10.0.0.1), open: tcp 8080 (http-proxy)", format_timestamp(1700172800)),
+			format!("{} |   since {}: tcp 22 closed", format_timestamp(1700172800), format_timestamp(1700000000)),
+			format!("{} |   since {}: tcp 8080 opened", format_timestamp(1700172800), format_timestamp(1700000000))
+		];
+		assert_eq!(store.host_history("GW.example").unwrap(), expected);
+		assert_eq!(store.host_history("10.0.0.1").unwrap(), expected);
+		// Addresses only match whole entries in the list, so 10.0.0.1 doesn't match 10.0.0.10.
+		assert!(store.host_history("10.0.0.").unwrap().iter().all(|x| x.ends_with("not in scan")));
+	}
+	
+	#[test]
+	fn tracks_ports_by_ip_address() {
+		let mut store = HistoryStore::open(":memory:").unwrap();
+		let scans = [
+			scan_at(1700000000, "10.0.0.0/24", &host(&["10.0.0.1"], Some("gw.example"), &[port(22, "open"), port(8080, "closed")].concat())),
+			scan_at(1700086400, "10.0.0.0/24", &host(&["10.0.0.1"], Some("gw.example"), &open(&[22, 8080]))),
+			// The host gained a MAC address and an IPv6 address, but it's still the same host.
+			scan_at(1700172800, "10.0.0.0/24", &host(&["10.0.0.1", "2001:db8::1", "00:11:22:33:44:55"], Some("gw.example"), &open(&[22, 8080]))),
+			scan_at(1700259200, "10.0.0.0/24", &host(&["10.0.0.2"], None, &open(&[8080])))
+		];
+		for (number, scan) in scans.iter().enumerate() {
+			store.add_scan(scan, &format!("{}.xml", number)).unwrap();
+		}
+		
+		let sightings = |host : Option<&str>, port : Option<u16>, state : &str| store.sightings(host, port, state).unwrap().iter().map(|x| (x.address.clone(), x.port, x.first_seen, x.last_seen, x.scans)).collect::<Vec<(String, u16, i64, i64, usize)>>();
+		assert_eq!(sightings(None, Some(8080), "open"), vec![
+			("10.0.0.1".to_string(), 8080, 1700086400, 1700172800, 2),
+			("2001:db8::1".to_string(), 8080, 1700172800, 1700172800, 1),
+			("10.0.0.2".to_string(), 8080, 1700259200, 1700259200, 1)
+		]);
+		assert_eq!(sightings(Some("gw.example"), None, "open").len(), 4);
+		assert_eq!(sightings(Some("10.0.0.2"), None, "open").len(), 1);
+		assert_eq!(sightings(None, None, "closed"), vec![("10.0.0.1".to_string(), 8080, 1700000000, 1700000000, 1)]);
+		
+		let sighting = &store.sightings(Some("10.0.0.1"), Some(22), "open").unwrap()[0];
+		assert_eq!(sighting.to_string(), format!("gw.example (10.0.0.1) tcp 22: first seen {}, last seen {}, in 3 scans", format_timestamp(1700000000), format_timestamp(1700172800)));
+	}
+	
+	#[test]
+	fn refuses_to_open_missing_stores_read_only() {
+		assert_eq!(HistoryStore::open_read_only("/nonexistent/history.db").err(), Some("'/nonexistent/history.db' doesn't exist".to_string()));
+	}
+}
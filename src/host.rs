@@ -1,13 +1,34 @@
 use std::fmt;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::str::FromStr;
+use chrono::DateTime;
 
-use nmap_xml_parser::NmapResults;
 use nmap_xml_parser::host::Host;
 use nmap_xml_parser::host::HostStatus;
+use nmap_xml_parser::host::HostState;
 use nmap_xml_parser::port::Port;
+use nmap_xml_parser::port::PortState;
+use nmap_xml_parser::port::PortProtocol;
 use nmap_xml_parser::host::Address;
 use nmap_xml_parser::host::Hostname;
 
+use crate::details::HostDetails;
+use crate::details::OsMatch;
+use crate::details::Route;
+use crate::details::ExtraPorts;
+use crate::details::ScanInfo;
+use crate::details::parse_port_state;
+use crate::details::covers;
+use crate::scan::Scan;
+use crate::scope::Scope;
+use crate::severity::PortEvent;
+use crate::severity::port_event;
+
 pub struct HostWrapper(pub Host);
+pub struct PortWrapper(pub Port);
+pub struct AddressWrapper(pub Address);
 pub struct HostStatusWrapper(pub HostStatus);
 pub struct PortsWrapper(pub Vec<Port>);
 pub struct AddressesWrapper(pub Vec<Address>);
@@ -15,30 +36,303 @@ pub struct HostnamesWrapper(pub Vec<Hostname>);
 
 impl HostWrapper {
 	pub fn get_title(&self) -> String {
-		let host_name = match self.0.host_names().next() { Some(x) => x.name.to_string(), None => "<no hostname>".to_string() };
-		let address = match self.0.addresses().next() {
-			Some(addr) => match addr {
-				Address::IpAddr(x) => x.to_string(),
-				Address::MacAddr(x) => x.to_string()
-			}
+		host_title(&[&self.0])
+	}
+	
+	/// Like get_title, but lists the addresses from both hosts, so that a dual-stack host that was scanned over IPv4 on one side
+	/// and IPv6 on the other shows both.
+	pub fn get_pair_title(&self, other : &Host) -> String {
+		host_title(&[&self.0, other])
+	}
+}
+
+/// Builds a title from the first hostname and the IP addresses of the given hosts (IPv4 first), falling back to a MAC address.
+fn host_title(hosts : &[&Host]) -> String {
+	let host_name = match hosts.iter().find_map(|x| x.host_names().next()) { Some(x) => x.name.to_string(), None => "<no hostname>".to_string() };
+	
+	let mut ips : Vec<IpAddr> = Vec::new();
+	for host in hosts {
+		for address in host.addresses() {
+			if let Address::IpAddr(x) = address && !ips.contains(x) { ips.push(*x); }
+		}
+	}
+	ips.sort_by_key(|x| x.is_ipv6());
+	
+	let address = match ips.is_empty() {
+		false => ips.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", "),
+		true => match hosts.iter().find_map(|x| x.addresses().next()) {
+			Some(x) => AddressWrapper(x.clone()).to_string(),
 			None => "<no address>".to_string()
-		};
-		format!("{} ({})", host_name, address)
+		}
+	};
+	format!("{} ({})", host_name, address)
+}
+
+/// Returns whether a host has any IPv4 and any IPv6 addresses.
+fn address_families(host : &Host) -> (bool, bool) {
+	let ipv4 = host.addresses().any(|x| matches!(x, Address::IpAddr(IpAddr::V4(_))));
+	let ipv6 = host.addresses().any(|x| matches!(x, Address::IpAddr(IpAddr::V6(_))));
+	(ipv4, ipv6)
+}
+
+/// Checks whether two hosts with no addresses in common share a hostname, and were scanned over different address families. A
+/// hostname that now points at a different address in the same family may well be a different machine, so that isn't a match.
+fn same_host_by_name(left : &Host, right : &Host) -> bool {
+	let (left_v4, left_v6) = address_families(left);
+	let (right_v4, right_v6) = address_families(right);
+	let same_family = (left_v4 && right_v4) || (left_v6 && right_v6);
+	
+	!same_family && (left_v4 || left_v6) && (right_v4 || right_v6)
+		&& left.host_names().any(|x| right.host_names().any(|y| y.name.eq_ignore_ascii_case(&x.name)))
+}
+
+/// An address that can be used as a map key, for pairing up hosts without comparing every host on one side with every host
+/// on the other.
+#[derive(PartialEq,Eq,Hash)]
+enum AddressKey<'a> {
+	Ip(IpAddr),
+	Mac(&'a str)
+}
+
+impl<'a> From<&'a Address> for AddressKey<'a> {
+	fn from(address : &'a Address) -> Self {
+		match address {
+			Address::IpAddr(x) => AddressKey::Ip(*x),
+			Address::MacAddr(x) => AddressKey::Mac(x)
+		}
+	}
+}
+
+/// Checks whether two hostnames are the same. DNS names are case-insensitive, so case is ignored.
+fn same_hostname(left : &Hostname, right : &Hostname) -> bool {
+	left.name.eq_ignore_ascii_case(&right.name) && left.source == right.source
+}
+
+impl PortsWrapper {
+	/// Returns a short description of each port, in the order they appear.
+	pub fn entries(&self) -> Vec<String> {
+		self.0.iter().map(|x| PortWrapper(x.clone()).to_string()).collect()
+	}
+}
+
+impl AddressesWrapper {
+	/// Returns each address as a string, in the order they appear.
+	pub fn entries(&self) -> Vec<String> {
+		self.0.iter().map(|x| AddressWrapper(x.clone()).to_string()).collect()
+	}
+	
+	/// Returns the IP addresses as strings, leaving out MAC addresses, sorted so that they don't depend on the order Nmap listed
+	/// them in.
+	pub fn sorted_ips(&self) -> Vec<String> {
+		let mut ips : Vec<IpAddr> = self.0.iter().filter_map(|x| match x { Address::IpAddr(ip) => Some(*ip), Address::MacAddr(_) => None }).collect();
+		ips.sort();
+		ips.iter().map(|x| x.to_string()).collect()
+	}
+}
+
+impl HostnamesWrapper {
+	/// Returns each hostname as a string, in the order they appear.
+	pub fn entries(&self) -> Vec<String> {
+		self.0.iter().map(|x| x.name.to_string()).collect()
+	}
+}
+
+/// How a single element of a host, such as a port or an address, differs between the two sides of a diff.
+#[derive(Debug,Clone,PartialEq)]
+pub enum Change<T> {
+	Added(T),
+	Removed(T),
+	Changed(T,T),
+	Same(T),
+	/// Listed on the left, but only counted in Nmap's extraports summary on the right, with the same state.
+	Collapsed(T),
+	/// Only counted in Nmap's extraports summary on the left, but listed on the right with the same state.
+	Expanded(T),
+	/// Listed on the left, but the right scan didn't cover it.
+	OnlyScannedLeft(T),
+	/// Listed on the right, but the left scan didn't cover it.
+	OnlyScannedRight(T)
+}
+
+impl<T> Change<T> {
+	/// Checks whether the element is the same on both sides, or only moved in or out of Nmap's extraports summary. These are
+	/// shown in the diff, but don't make a host count as changed.
+	pub fn is_informational(&self) -> bool {
+		matches!(self, Change::Same(_) | Change::Collapsed(_) | Change::Expanded(_))
+	}
+	
+	/// Returns the element as it is on the right side, or on the only side that has it.
+	pub fn latest(&self) -> &T {
+		match self {
+			Change::Changed(_, x) => x,
+			Change::Added(x) | Change::Removed(x) | Change::Same(x) | Change::Collapsed(x) | Change::Expanded(x) | Change::OnlyScannedLeft(x) | Change::OnlyScannedRight(x) => x
+		}
+	}
+}
+
+/// Pairs up the elements on each side using `matches`, then classifies each pair.
+fn compare_elements<T : Clone + PartialEq>(left : &[T], right : &[T], matches : impl Fn(&T, &T) -> bool) -> Vec<Change<T>> {
+	let mut output : Vec<Change<T>> = Vec::new();
+	
+	for item in left {
+		match right.iter().find(|other| matches(item, other)) {
+			Some(other) if other == item => output.push(Change::Same(item.clone())),
+			Some(other) => output.push(Change::Changed(item.clone(), other.clone())),
+			None => output.push(Change::Removed(item.clone()))
+		}
+	}
+	
+	for item in right {
+		if !left.iter().any(|other| matches(other, item)) {
+			output.push(Change::Added(item.clone()));
+		}
+	}
+	
+	output
+}
+
+/// Settings which control what counts as a change when diffing two scans.
+#[derive(Debug,Clone)]
+pub struct DiffOptions {
+	/// OS guesses whose accuracy moves by no more than this many percentage points are considered unchanged.
+	pub os_accuracy_threshold: u8,
+	/// Last boot times which move by no more than this many seconds are considered unchanged, since Nmap's uptime guesses drift.
+	pub reboot_tolerance: i64,
+	/// Whether to report changes in network distance and traceroute path.
+	pub compare_routes: bool,
+	/// Groups of port states which are considered equivalent, e.g. so that a port flapping between filtered and open|filtered isn't reported.
+	pub state_classes: StateClasses,
+	/// What to do with ports that only one of the scans covered.
+	pub coverage: CoveragePolicy,
+	/// Whether hosts that a scan reported as down count as being present in it.
+	pub down_hosts: DownHostPolicy,
+	/// The networks to limit the diff to.
+	pub scope: Scope
+}
+
+impl Default for DiffOptions {
+	fn default() -> Self {
+		Self {
+			os_accuracy_threshold: 5,
+			reboot_tolerance: 300,
+			compare_routes: false,
+			state_classes: StateClasses::default(),
+			coverage: CoveragePolicy::Flag,
+			down_hosts: DownHostPolicy::Present,
+			scope: Scope::default()
+		}
+	}
+}
+
+/// How to treat changes to ports which were only covered by one of the two scans, e.g. because one used `-p 1-1000` and the other `-p-`.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum CoveragePolicy {
+	/// Report them as ordinary additions and removals.
+	Ignore,
+	/// Report them, but mark them as only having been scanned on one side.
+	Flag,
+	/// Don't report them at all.
+	Suppress
+}
+
+impl FromStr for CoveragePolicy {
+	type Err = String;
+	
+	fn from_str(s : &str) -> Result<Self,Self::Err> {
+		match s {
+			"ignore" => Ok(CoveragePolicy::Ignore),
+			"flag" => Ok(CoveragePolicy::Flag),
+			"suppress" => Ok(CoveragePolicy::Suppress),
+			_ => Err(format!("unknown coverage policy '{}' (expected ignore, flag or suppress)", s))
+		}
+	}
+}
+
+/// How to treat hosts that a scan reported as down. Whether down hosts appear in the output at all depends on options like `-Pn`,
+/// so without a policy the same host could show up as a status change in one pair of scans and as gone or new in another.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum DownHostPolicy {
+	/// Down hosts are present, so a host that goes down is reported as a status change and one that is down in one scan and
+	/// missing from the other is reported as gone or new. This is the default, and matches how hosts were always compared.
+	Present,
+	/// Down hosts are treated as if they weren't in the scan, so a host that goes down is reported as gone and one that is down
+	/// in one scan and missing from the other isn't reported at all.
+	Absent
+}
+
+impl DownHostPolicy {
+	pub fn counts(&self, host : &Host) -> bool {
+		match self {
+			DownHostPolicy::Present => true,
+			DownHostPolicy::Absent => host.status.state != HostState::Down
+		}
+	}
+}
+
+impl FromStr for DownHostPolicy {
+	type Err = String;
+	
+	fn from_str(s : &str) -> Result<Self,Self::Err> {
+		match s {
+			"present" => Ok(DownHostPolicy::Present),
+			"absent" => Ok(DownHostPolicy::Absent),
+			_ => Err(format!("unknown down host policy '{}' (expected present or absent)", s))
+		}
+	}
+}
+
+/// Groups of port states which are considered equivalent when diffing. With no groups, every state is only equivalent to itself.
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct StateClasses(pub Vec<Vec<PortState>>);
+
+impl StateClasses {
+	/// Treats every state other than open as equivalent, so that only ports becoming open or ceasing to be open are reported.
+	pub fn exposure() -> StateClasses {
+		StateClasses(vec![vec![PortState::Closed, PortState::Filtered, PortState::Unfiltered, PortState::OpenFiltered, PortState::CloseFiltered]])
+	}
+	
+	/// Parses a comma-separated list of Nmap port states (e.g. "filtered,open|filtered") into a single class.
+	pub fn parse_class(class : &str) -> Result<Vec<PortState>,String> {
+		class.split(',').map(|x| {
+			parse_port_state(x.trim()).ok_or(format!("unknown port state '{}'", x.trim()))
+		}).collect()
+	}
+	
+	pub fn equivalent(&self, left : &PortState, right : &PortState) -> bool {
+		left == right || self.0.iter().any(|class| class.contains(left) && class.contains(right))
+	}
+	
+	/// Checks whether a state is in a class without open in it. A port which appears or disappears in such a state has only
+	/// moved between states that aren't open, since a port that isn't listed isn't open either.
+	pub fn not_open(&self, state : &PortState) -> bool {
+		self.0.iter().any(|class| class.contains(state) && !class.contains(&PortState::Open))
 	}
 }
 
 #[derive(Debug,Clone)]
 pub struct HostDiff {
 	pub title: String,
+	pub left: Host,
+	pub right: Host,
 	pub status: Option<(HostStatus,HostStatus)>,
 	pub ports: Option<(Vec<Port>,Vec<Port>)>,
 	pub addresses: Option<(Vec<Address>,Vec<Address>)>,
-	pub hostnames: Option<(Vec<Hostname>,Vec<Hostname>)>
+	pub hostnames: Option<(Vec<Hostname>,Vec<Hostname>)>,
+	pub os: Option<(OsMatch,OsMatch)>,
+	pub reboot: Option<(i64,i64)>,
+	pub route: Option<(Route,Route)>,
+	/// The extraports summaries on each side, which are used to tell ports that disappeared from those that were collapsed.
+	pub extraports: (Vec<ExtraPorts>,Vec<ExtraPorts>),
+	/// The ports each scan covered, which are used to tell ports that disappeared from those that weren't scanned.
+	pub coverage: (Vec<ScanInfo>,Vec<ScanInfo>),
+	pub coverage_policy: CoveragePolicy,
+	/// The state classes the diff was made with, so that ports which only moved within a class aren't reported as changed.
+	pub state_classes: StateClasses
 }
 
 impl HostDiff {
 	pub fn from_hosts(left : &Host, right : &Host) -> HostDiff {
-		let title = HostWrapper(right.clone()).get_title();
+		let title = HostWrapper(right.clone()).get_pair_title(left);
 	
 		let status = match HostStatusWrapper(left.status.clone()) == HostStatusWrapper(right.status.clone()) {
 			false => Some((left.status.clone(), right.status.clone())),
@@ -68,10 +362,19 @@ impl HostDiff {
 				
 		let diff = HostDiff {
 			title: title,
+			left: left.clone(),
+			right: right.clone(),
 			status: status,
 			ports: ports,
 			addresses: addresses,
-			hostnames: hostnames
+			hostnames: hostnames,
+			os: None,
+			reboot: None,
+			route: None,
+			extraports: (Vec::new(), Vec::new()),
+			coverage: (Vec::new(), Vec::new()),
+			coverage_policy: CoveragePolicy::Ignore,
+			state_classes: StateClasses::default()
 		};
 		
 		// Commented out because it's actually more confusing if we omit the "unchanged" ports.
@@ -80,9 +383,164 @@ impl HostDiff {
 		diff
 	}
 	
-	/// Check if the two sides of the diff are identical.
+	/// Check if the two sides of the diff are identical. Ports which were only collapsed into extraports or expanded from them
+	/// don't count as changes.
 	pub fn is_unchanged(&self) -> bool {
-		self.status.is_none() && self.ports.is_none() && self.addresses.is_none() && self.hostnames.is_none()
+		let ports_unchanged = self.port_changes().iter().all(|x| x.is_informational());
+		self.status.is_none() && ports_unchanged && self.addresses.is_none() && self.hostnames.is_none() && self.os.is_none() && self.reboot.is_none() && self.route.is_none()
+	}
+	
+	/// Compares the best OS guess on each side, ignoring small changes in accuracy. Hosts without an OS guess on both sides are never considered changed.
+	pub fn compare_os(&mut self, left : &HostDetails, right : &HostDetails, options : &DiffOptions) {
+		self.os = match (left.os_matches.first(), right.os_matches.first()) {
+			(Some(left_os), Some(right_os)) => {
+				let classes_differ = left_os.classes.first().map(|x| x.key()) != right_os.classes.first().map(|x| x.key());
+				let accuracy_differs = left_os.accuracy.abs_diff(right_os.accuracy) > options.os_accuracy_threshold;
+				match left_os.name != right_os.name || classes_differ || accuracy_differs {
+					true => Some((left_os.clone(), right_os.clone())),
+					false => None
+				}
+			},
+			_ => None
+		};
+	}
+	
+	/// Compares the ports on each side by protocol and port number. Empty if the ports haven't changed. Ports which kept their
+	/// service and only moved to another state in the same class count as the same, as do ports which appeared or disappeared
+	/// in a state from a class without open in it.
+	pub fn port_changes(&self) -> Vec<Change<Port>> {
+		let service_name = |port : &Port| port.service_info.as_ref().map(|x| x.name.clone());
+		let equivalent = |left : &Port, right : &Port| left.status.state != right.status.state
+			&& self.state_classes.equivalent(&left.status.state, &right.status.state)
+			&& service_name(left) == service_name(right);
+		let unscanned = |scan_info : &Vec<ScanInfo>, port : &Port| self.coverage_policy != CoveragePolicy::Ignore && !covers(scan_info, &port.protocol, port.port_number);
+		
+		let changes = match &self.ports {
+			Some(ports) => compare_elements(&ports.0, &ports.1, |a, b| a.protocol == b.protocol && a.port_number == b.port_number),
+			None => Vec::new()
+		};
+		
+		// A listed port can only have been collapsed into extraports if the other side's extraports count for its state grew by
+		// at least the number of listed ports in that state which disappeared, and likewise for ports expanded out of them.
+		let count = |extraports : &Vec<ExtraPorts>, state : &PortState| extraports.iter().filter(|x| &x.state == state).map(|x| x.count).sum::<u32>();
+		let missing = |state : &PortState, removed : bool| changes.iter().filter(|x| match x {
+			Change::Removed(port) => removed && &port.status.state == state,
+			Change::Added(port) => !removed && &port.status.state == state,
+			_ => false
+		}).count() as u32;
+		let collapsed = |port : &Port| {
+			let (before, after) = (count(&self.extraports.0, &port.status.state), count(&self.extraports.1, &port.status.state));
+			after > 0 && after >= before + missing(&port.status.state, true)
+		};
+		let expanded = |port : &Port| {
+			let (before, after) = (count(&self.extraports.0, &port.status.state), count(&self.extraports.1, &port.status.state));
+			before > 0 && before >= after + missing(&port.status.state, false)
+		};
+		
+		let changes : Vec<Change<Port>> = changes.iter().cloned().map(|change| match change {
+			Change::Removed(port) if collapsed(&port) => Change::Collapsed(port),
+			Change::Added(port) if expanded(&port) => Change::Expanded(port),
+			Change::Added(port) | Change::Removed(port) if self.state_classes.not_open(&port.status.state) => Change::Same(port),
+			Change::Removed(port) if unscanned(&self.coverage.1, &port) => Change::OnlyScannedLeft(port),
+			Change::Added(port) if unscanned(&self.coverage.0, &port) => Change::OnlyScannedRight(port),
+			Change::Changed(left, right) if equivalent(&left, &right) => Change::Same(right),
+			other => other
+		}).collect();
+		
+		match self.coverage_policy {
+			CoveragePolicy::Suppress => changes.into_iter().filter(|x| !matches!(x, Change::OnlyScannedLeft(_) | Change::OnlyScannedRight(_))).collect(),
+			_ => changes
+		}
+	}
+	
+	/// Compares the addresses on each side. Empty if the addresses haven't changed.
+	pub fn address_changes(&self) -> Vec<Change<Address>> {
+		match &self.addresses {
+			Some(addresses) => compare_elements(&addresses.0, &addresses.1, |a, b| a == b),
+			None => Vec::new()
+		}
+	}
+	
+	/// Compares the hostnames on each side by name, ignoring case. Empty if the hostnames haven't changed.
+	pub fn hostname_changes(&self) -> Vec<Change<Hostname>> {
+		let changes = match &self.hostnames {
+			Some(hostnames) => compare_elements(&hostnames.0, &hostnames.1, |a, b| a.name.eq_ignore_ascii_case(&b.name)),
+			None => Vec::new()
+		};
+		changes.into_iter().map(|change| match change {
+			Change::Changed(left, right) if same_hostname(&left, &right) => Change::Same(right),
+			other => other
+		}).collect()
+	}
+	
+	/// Checks whether the host rebooted between the two scans, by comparing the last boot time on each side.
+	pub fn compare_uptime(&mut self, left : &HostDetails, right : &HostDetails, options : &DiffOptions) {
+		let left_boot = left.uptime.as_ref().and_then(|x| x.last_boot);
+		let right_boot = right.uptime.as_ref().and_then(|x| x.last_boot);
+		
+		self.reboot = match (left_boot, right_boot) {
+			(Some(left_boot), Some(right_boot)) if right_boot > left_boot + options.reboot_tolerance => Some((left_boot, right_boot)),
+			_ => None
+		};
+	}
+	
+	/// Compares the network distance and traceroute path on each side, if route comparison is enabled and both sides have route information.
+	pub fn compare_route(&mut self, left : &HostDetails, right : &HostDetails, options : &DiffOptions) {
+		self.route = match options.compare_routes && !left.route.is_empty() && !right.route.is_empty() && left.route.differs(&right.route) {
+			true => Some((left.route.clone(), right.route.clone())),
+			false => None
+		};
+	}
+	
+	/// Records the extraports summaries on each side, so that ports which moved in or out of them aren't reported as removed or added.
+	pub fn compare_extraports(&mut self, left : &HostDetails, right : &HostDetails) {
+		self.extraports = (left.extraports.clone(), right.extraports.clone());
+	}
+	
+	/// Records the ports each scan covered, so that ports which only one scan covered can be flagged or suppressed.
+	pub fn compare_coverage(&mut self, left : &[ScanInfo], right : &[ScanInfo], options : &DiffOptions) {
+		self.coverage = (left.to_vec(), right.to_vec());
+		self.coverage_policy = options.coverage;
+	}
+	
+	/// Ignores the port changes if every port kept its service and stayed within the same class of states. Ports which were
+	/// collapsed into extraports (or expanded from them) are kept, so that they can still be reported.
+	pub fn compare_port_states(&mut self, options : &DiffOptions) {
+		self.state_classes = options.state_classes.clone();
+		if self.port_changes().iter().all(|change| matches!(change, Change::Same(_))) { self.ports = None; }
+	}
+	
+	/// Removes the ports with changes matching `suppressed` from both sides, returning how many were removed.
+	pub fn suppress_ports(&mut self, suppressed : impl Fn(&Change<Port>) -> bool) -> usize {
+		let removed : Vec<(PortProtocol,u16)> = self.port_changes().iter()
+			.filter(|x| !x.is_informational() && suppressed(x))
+			.map(|x| (x.latest().protocol.clone(), x.latest().port_number))
+			.collect();
+		
+		if let Some(ports) = &self.ports {
+			let keep = |port : &Port| !removed.contains(&(port.protocol.clone(), port.port_number));
+			let left_ports : Vec<Port> = ports.0.iter().filter(|x| keep(x)).cloned().collect();
+			let right_ports : Vec<Port> = ports.1.iter().filter(|x| keep(x)).cloned().collect();
+			self.ports = match PortsWrapper(left_ports.clone()) == PortsWrapper(right_ports.clone()) {
+				false => Some((left_ports, right_ports)),
+				true => None
+			};
+		}
+		
+		if self.port_changes().iter().all(|x| matches!(x, Change::Same(_))) { self.ports = None; }
+		
+		removed.len()
+	}
+	
+	/// Turns the diff into a delta, which is unchanged if nothing but informational port changes are left.
+	pub fn into_delta(self) -> HostDelta {
+		match self.is_unchanged() {
+			true => {
+				let notes = self.port_changes().into_iter().filter(|x| matches!(x, Change::Collapsed(_) | Change::Expanded(_))).collect();
+				HostDelta::Unchanged(self.right, notes)
+			},
+			false => HostDelta::Changed(Box::new(self))
+		}
 	}
 	
 	/// Removes all ports that are exactly identical from the right side of a diff.
@@ -109,54 +567,172 @@ impl HostDiff {
 
 #[derive(Debug,Clone)]
 pub enum HostDelta {
-	Changed(HostDiff),
-	Unchanged(Host),
+	Changed(Box<HostDiff>),
+	/// A host with no changes, along with any of its ports which were only collapsed into extraports or expanded from them.
+	Unchanged(Host,Vec<Change<Port>>),
 	Gone(Host),
 	New(Host)
 }
 
+/// The kind of change a HostDelta represents, without any of the underlying data.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+pub enum DeltaKind {
+	Changed,
+	Unchanged,
+	Gone,
+	New
+}
+
 impl HostDelta {
-	pub fn from_scans(old : &NmapResults, new : &NmapResults) -> Vec<HostDelta> {
+	pub fn kind(&self) -> DeltaKind {
+		match self {
+			HostDelta::Changed(_) => DeltaKind::Changed,
+			HostDelta::Unchanged(_, _) => DeltaKind::Unchanged,
+			HostDelta::Gone(_) => DeltaKind::Gone,
+			HostDelta::New(_) => DeltaKind::New
+		}
+	}
+	
+	/// Returns the most recent version of the host, i.e. the right side unless the host is gone.
+	pub fn host(&self) -> &Host {
+		match self {
+			HostDelta::Changed(diff) => &diff.right,
+			HostDelta::Unchanged(host, _) => host,
+			HostDelta::Gone(host) => host,
+			HostDelta::New(host) => host
+		}
+	}
+	
+	/// Returns the left and right versions of the host, if the host is present on that side.
+	pub fn sides(&self) -> (Option<&Host>, Option<&Host>) {
+		match self {
+			HostDelta::Changed(diff) => (Some(&diff.left), Some(&diff.right)),
+			HostDelta::Unchanged(host, _) => (Some(host), Some(host)),
+			HostDelta::Gone(host) => (Some(host), None),
+			HostDelta::New(host) => (None, Some(host))
+		}
+	}
+	
+	/// Returns the ports that are open on the right side but weren't open on the left side.
+	pub fn opened_ports(&self) -> Vec<Port> {
+		self.ports_with_event(PortEvent::Opened)
+	}
+	
+	/// Returns the ports that were open on the left side but aren't open on the right side.
+	pub fn closed_ports(&self) -> Vec<Port> {
+		self.ports_with_event(PortEvent::Closed)
+	}
+	
+	/// Returns the ports that `port_event` puts down to the given event, so that every report agrees on which ports opened
+	/// and closed.
+	fn ports_with_event(&self, event : PortEvent) -> Vec<Port> {
+		self.port_changes().iter().filter_map(|change| match port_event(change) {
+			Some((port, x)) if x == event => Some(port.clone()),
+			_ => None
+		}).collect()
+	}
+	
+	/// Returns every port change, treating all of the ports of a new or gone host as added or removed.
+	pub fn port_changes(&self) -> Vec<Change<Port>> {
+		match self {
+			HostDelta::Changed(diff) => diff.port_changes(),
+			HostDelta::New(host) => host.port_info.ports().map(|x| Change::Added(x.clone())).collect(),
+			HostDelta::Gone(host) => host.port_info.ports().map(|x| Change::Removed(x.clone())).collect(),
+			HostDelta::Unchanged(_, _) => Vec::new()
+		}
+	}
+	
+	pub fn from_scans(old_scan : &Scan, new_scan : &Scan, options : &DiffOptions) -> Vec<HostDelta> {
+		let included = |host : &&Host| options.down_hosts.counts(host) && options.scope.contains(host);
+		let old : Vec<&Host> = old_scan.results.hosts().filter(included).collect();
+		let new : Vec<&Host> = new_scan.results.hosts().filter(included).collect();
 		let mut output : Vec<HostDelta> = Vec::new();
 		
-		// Iterate through the old scan and identify any hosts that don't exist in the new scan.
-		for old_host in old.hosts() {
-			if !new.hosts().any(|x| { HostWrapper(old_host.clone()) == HostWrapper(x.clone()) }) {
-				let gone = HostDelta::Gone(old_host.clone());
-				output.push(gone);
+		// Index the hosts on each side by address, so that they can be paired up without comparing every host with every other.
+		let mut old_by_address : HashMap<AddressKey, Vec<usize>> = HashMap::new();
+		for (index, host) in old.iter().enumerate() {
+			for address in host.addresses() { old_by_address.entry(AddressKey::from(address)).or_default().push(index); }
+		}
+		let new_addresses : HashSet<AddressKey> = new.iter().flat_map(|host| host.addresses().map(AddressKey::from)).collect();
+		
+		// Pair up each new host with the first old host that shares an address with it, using every old host at most once.
+		let mut old_used = vec![false; old.len()];
+		let mut new_used = vec![false; new.len()];
+		let mut pairs : Vec<(usize, usize)> = Vec::new();
+		for (new_index, host) in new.iter().enumerate() {
+			let candidate = host.addresses()
+				.filter_map(|x| old_by_address.get(&AddressKey::from(x)))
+				.flat_map(|x| x.iter().copied().find(|index| !old_used[*index]))
+				.min();
+			if let Some(old_index) = candidate {
+				old_used[old_index] = true;
+				new_used[new_index] = true;
+				pairs.push((old_index, new_index));
 			}
 		}
 		
-		// Iterate through the new scan and identify any hosts that don't exist in the old scan (eliminating them).
-		let mut remaining_hosts : Vec<Host> = new.hosts().map(|x| { x.clone() }).collect();
-		remaining_hosts.retain(|host| {
-			if !old.hosts().any(|x| { HostWrapper(host.clone()) == HostWrapper(x.clone()) }) {
-				let new = HostDelta::New(host.clone());
-				output.push(new);
-				return false;
+		// Hosts that share no address with anything on the other side may still be a dual-stack host that was scanned over IPv4 on
+		// one side and IPv6 on the other, so those are paired up by hostname instead.
+		let mut unmatched_old_by_name : HashMap<String, Vec<usize>> = HashMap::new();
+		for (index, host) in old.iter().enumerate() {
+			if host.addresses().any(|x| new_addresses.contains(&AddressKey::from(x))) { continue; }
+			for hostname in host.host_names() {
+				let indices = unmatched_old_by_name.entry(hostname.name.to_ascii_lowercase()).or_default();
+				if indices.last() != Some(&index) { indices.push(index); }
 			}
+		}
+		for (new_index, host) in new.iter().enumerate() {
+			if host.addresses().any(|x| old_by_address.contains_key(&AddressKey::from(x))) { continue; }
+			let candidate = host.host_names()
+				.filter_map(|x| unmatched_old_by_name.get(&x.name.to_ascii_lowercase()))
+				.flat_map(|x| x.iter().copied().find(|index| !old_used[*index] && same_host_by_name(old[*index], host)))
+				.min();
+			if let Some(old_index) = candidate {
+				old_used[old_index] = true;
+				new_used[new_index] = true;
+				pairs.push((old_index, new_index));
+			}
+		}
 		
-			true
-		});
-		
-		// Iterate through the remaining "changed" hosts and generate a HostDiff for them.
-		for host in remaining_hosts {
-			for old_host in old.hosts() {
-				if HostWrapper(host.clone()) == HostWrapper(old_host.clone()) {
-					let diff = HostDiff::from_hosts(old_host, &host);
-					let changed = match diff.is_unchanged() {
-						true => HostDelta::Unchanged(host.clone()),
-						false => HostDelta::Changed(diff)
-					};
-					output.push(changed);
-				}
+		// Any host that wasn't paired up has either gone or is new.
+		for (old_index, old_host) in old.iter().enumerate() {
+			if !old_used[old_index] {
+				output.push(HostDelta::Gone((*old_host).clone()));
+			}
+		}
+		for (new_index, new_host) in new.iter().enumerate() {
+			if !new_used[new_index] {
+				output.push(HostDelta::New((*new_host).clone()));
 			}
 		}
+		
+		// Generate a HostDiff for each pair of hosts.
+		for (old_index, new_index) in pairs {
+			let (old_host, host) = (old[old_index], new[new_index]);
+			let mut diff = HostDiff::from_hosts(old_host, host);
+			let old_details = old_scan.details.host(old_host).cloned().unwrap_or_default();
+			let new_details = new_scan.details.host(host).cloned().unwrap_or_default();
+			diff.compare_os(&old_details, &new_details, options);
+			diff.compare_uptime(&old_details, &new_details, options);
+			diff.compare_route(&old_details, &new_details, options);
+			diff.compare_extraports(&old_details, &new_details);
+			diff.compare_coverage(&old_scan.details.scan_info, &new_scan.details.scan_info, options);
+			diff.compare_port_states(options);
+			
+			output.push(diff.into_delta());
+		}
 	
 		output
 	}
 }
 
+pub fn format_timestamp(timestamp : i64) -> String {
+	match DateTime::from_timestamp(timestamp, 0) {
+		Some(x) => format!("{}", x),
+		None => "<unknown time>".to_string()
+	}
+}
+
 // EQUALITY IMPLEMENTATIONS
 
 impl PartialEq for HostWrapper {
@@ -193,23 +769,18 @@ impl PartialEq for PortsWrapper {
 
 impl PartialEq for AddressesWrapper {
 	fn eq(&self, other: &AddressesWrapper) -> bool {
-		// Compare the addresses, irrespective of order, for equality.
-		let mut eq = true;
-		for address in &self.0 {
-			if !other.0.contains(&address) { eq = false; }
-		}
-		eq
+		// Compare the addresses, irrespective of order, for equality. Both sides are checked, so that an address which was
+		// gained counts as a change as well as one which was lost.
+		self.0.iter().all(|x| other.0.contains(x)) && other.0.iter().all(|x| self.0.contains(x))
 	}
 }
 
 impl PartialEq for HostnamesWrapper {
 	fn eq(&self, other: &HostnamesWrapper) -> bool {
-		// Compare the hostnames, irrespective of order, for equality.
-		let mut eq = true;
-		for hostname in &self.0 {
-			if !other.0.contains(&hostname) { eq = false; }
-		}
-		eq
+		// Compare the hostnames, irrespective of order, for equality. Both sides are checked, so that a hostname which was
+		// gained counts as a change as well as one which was lost.
+		let within = |left : &[Hostname], right : &[Hostname]| left.iter().all(|x| right.iter().any(|y| same_hostname(x, y)));
+		within(&self.0, &other.0) && within(&other.0, &self.0)
 	}
 }
 
@@ -232,16 +803,28 @@ impl fmt::Display for HostWrapper {
 	}
 }
 
-impl fmt::Display for PortsWrapper {
+impl fmt::Display for PortWrapper {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		let mut output : Vec<String> = Vec::new();
-		for port in &self.0 {
-			let mut port_str = format!("{} {} ({})", port.protocol.to_string(), port.port_number, port.status.reason);
-			if let Some(serviceinfo) = &port.service_info {
-				port_str.push_str(format!(" [{}]", serviceinfo.name).as_str());
-			}
-			output.push(port_str);
+		write!(f, "{} {} ({})", self.0.protocol, self.0.port_number, self.0.status.reason)?;
+		if let Some(serviceinfo) = &self.0.service_info {
+			write!(f, " [{}]", serviceinfo.name)?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Display for AddressWrapper {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.0 {
+			Address::IpAddr(x) => write!(f, "{}", x),
+			Address::MacAddr(x) => write!(f, "{}", x)
 		}
+	}
+}
+
+impl fmt::Display for PortsWrapper {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let output : Vec<String> = self.entries();
 		
 		if output.len() == 0 {
 			return write!(f, "<nothing to show>");
@@ -253,14 +836,7 @@ impl fmt::Display for PortsWrapper {
 
 impl fmt::Display for AddressesWrapper {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		let mut output : Vec<String> = Vec::new();
-		for address in &self.0 {
-			let addr_str = match address {
-				Address::IpAddr(x) => x.to_string(),
-				Address::MacAddr(x) => x.to_string()
-			};
-			output.push(addr_str);
-		}
+		let output : Vec<String> = self.entries();
 
 		if output.len() == 0 {
 			return write!(f, "<nothing to show>");
@@ -272,10 +848,7 @@ impl fmt::Display for AddressesWrapper {
 
 impl fmt::Display for HostnamesWrapper {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		let mut output : Vec<String> = Vec::new();
-		for hostname in &self.0 {
-			output.push(hostname.name.to_string());
-		}
+		let output : Vec<String> = self.entries();
 
 		if output.len() == 0 {
 			return write!(f, "<nothing to show>");
@@ -295,6 +868,18 @@ impl fmt::Display for HostDiff {
 			let left = PortsWrapper(ports.0.clone());
 			let right = PortsWrapper(ports.1.clone());
 			write!(f, "| Ports: {} => {}\n", left.to_string(), right.to_string())?;
+			
+			let changes = self.port_changes();
+			write!(f, "{}", extraports_notes(&changes))?;
+			
+			let only_left : Vec<String> = changes.iter().filter_map(|x| match x { Change::OnlyScannedLeft(port) => Some(format!("{} {}", port.protocol, port.port_number)), _ => None }).collect();
+			let only_right : Vec<String> = changes.iter().filter_map(|x| match x { Change::OnlyScannedRight(port) => Some(format!("{} {}", port.protocol, port.port_number)), _ => None }).collect();
+			if !only_left.is_empty() {
+				writeln!(f, "| Not covered by the right scan: {}", only_left.join(", "))?;
+			}
+			if !only_right.is_empty() {
+				writeln!(f, "| Not covered by the left scan: {}", only_right.join(", "))?;
+			}
 		}
 		
 		if let Some(addresses) = &self.addresses {
@@ -309,18 +894,400 @@ impl fmt::Display for HostDiff {
 			write!(f, "| Hostnames: {} => {}\n", left.to_string(), right.to_string())?;
 		}
 		
+		if let Some(os) = &self.os {
+			writeln!(f, "| OS: {} => {}", os.0, os.1)?;
+		}
+		
+		if let Some(reboot) = &self.reboot {
+			writeln!(f, "| Rebooted between scans: last boot {} => {}", format_timestamp(reboot.0), format_timestamp(reboot.1))?;
+		}
+		
+		if let Some(route) = &self.route {
+			writeln!(f, "| Route: {} => {}", route.0, route.1)?;
+		}
+		
 		Ok(())
 	}
 }
 
+/// Describes the ports which were only collapsed into extraports or expanded from them, one line for each.
+fn extraports_notes(changes : &[Change<Port>]) -> String {
+	let mut output = String::new();
+	let collapsed : Vec<String> = changes.iter().filter_map(|x| match x { Change::Collapsed(port) => Some(format!("{} {} ({})", port.protocol, port.port_number, port.status.state)), _ => None }).collect();
+	let expanded : Vec<String> = changes.iter().filter_map(|x| match x { Change::Expanded(port) => Some(format!("{} {} ({})", port.protocol, port.port_number, port.status.state)), _ => None }).collect();
+	if !collapsed.is_empty() {
+		output.push_str(&format!("| Collapsed into extraports: {}\n", collapsed.join(", ")));
+	}
+	if !expanded.is_empty() {
+		output.push_str(&format!("| Expanded from extraports: {}\n", expanded.join(", ")));
+	}
+	output
+}
+
+impl fmt::Display for DeltaKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let display_str = match self {
+			DeltaKind::Changed => "Changed",
+			DeltaKind::Unchanged => "Unchanged",
+			DeltaKind::Gone => "Gone",
+			DeltaKind::New => "New"
+		};
+		write!(f, "{}", display_str)
+	}
+}
+
 impl fmt::Display for HostDelta {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		let display_str = match &self {
 			HostDelta::Changed(x) => format!("[*] Changed Host: {}\n{}\n", x.title, x.to_string()),
-			HostDelta::Unchanged(x) => format!("[-] Unchanged Host: {}\n{}\n", HostWrapper(x.clone()).get_title(), HostWrapper(x.clone()).to_string()),
+			HostDelta::Unchanged(x, notes) => format!("[-] Unchanged Host: {}\n{}{}\n", HostWrapper(x.clone()).get_title(), extraports_notes(notes), HostWrapper(x.clone())),
 			HostDelta::Gone(x) => format!("[?] Gone Host: {}\n\n", HostWrapper(x.clone()).get_title()),
 			HostDelta::New(x) => format!("[+] New Host: {}\n{}\n", HostWrapper(x.clone()).get_title(), HostWrapper(x.clone()).to_string())
 		};
 		write!(f, "{}", display_str)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	
+	fn kinds(deltas : &[HostDelta]) -> Vec<DeltaKind> {
+		let mut kinds : Vec<DeltaKind> = deltas.iter().map(|x| x.kind()).collect();
+		kinds.sort();
+		kinds
+	}
+	
+	#[test]
+	fn ignores_ports_moving_between_states_that_are_not_open() {
+		let left = scan(&host(&["10.0.0.1"], None, &port(22, "filtered")));
+		let right = scan(&host(&["10.0.0.1"], None, &[port(22, "open|filtered"), port(9090, "filtered")].concat()));
+		let options = DiffOptions { state_classes: StateClasses::exposure(), ..Default::default() };
+		
+		let deltas = HostDelta::from_scans(&left, &right, &options);
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Unchanged]);
+		
+		// Without the classes, both ports are changes.
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed]);
+		assert_eq!(deltas[0].port_changes().iter().filter(|x| !x.is_informational()).count(), 2);
+	}
+	
+	#[test]
+	fn reports_ports_opening_despite_state_classes() {
+		let left = scan(&host(&["10.0.0.1"], None, &port(22, "filtered")));
+		let right = scan(&host(&["10.0.0.1"], None, &[port(22, "open|filtered"), port(8080, "open")].concat()));
+		let options = DiffOptions { state_classes: StateClasses::exposure(), ..Default::default() };
+		
+		let deltas = HostDelta::from_scans(&left, &right, &options);
+		assert_eq!(deltas[0].opened_ports().iter().map(|x| x.port_number).collect::<Vec<u16>>(), vec![8080]);
+		let changes : Vec<u16> = deltas[0].port_changes().iter().filter(|x| !x.is_informational()).map(|x| x.latest().port_number).collect();
+		assert_eq!(changes, vec![8080]);
+	}
+	
+	#[test]
+	fn only_forgives_state_changes_within_a_class() {
+		let identified = |method : &str| format!(r#"<port protocol="tcp" portid="80"><state state="open" reason="syn-ack" reason_ttl="64"/><service name="http" method="{}" conf="10"/></port>"#, method);
+		let left = scan(&host(&["10.0.0.1"], None, &[port(22, "filtered"), port(443, "closed"), identified("table")].concat()));
+		let right = scan(&host(&["10.0.0.1"], None, &[port(22, "open|filtered"), port(443, "filtered"), identified("probed")].concat()));
+		let changes = |options : &DiffOptions| {
+			let deltas = HostDelta::from_scans(&left, &right, options);
+			deltas[0].port_changes().iter().filter(|x| !x.is_informational()).map(|x| x.latest().port_number).collect::<Vec<u16>>()
+		};
+		
+		// A class of states without open in it only forgives ports moving within it, and changes to ports which stayed in the
+		// same state are still reported, just as they are with no classes.
+		let options = DiffOptions { state_classes: StateClasses(vec![StateClasses::parse_class("filtered,open|filtered").unwrap()]), ..Default::default() };
+		assert_eq!(changes(&options), vec![443, 80]);
+		assert_eq!(changes(&DiffOptions::default()), vec![22, 443, 80]);
+	}
+	
+	/// Returns the port changes that make the host count as changed, along with the ports that were collapsed and expanded.
+	fn port_summary(delta : &HostDelta) -> (Vec<u16>, Vec<u16>, Vec<u16>) {
+		let changes = match delta {
+			HostDelta::Unchanged(_, notes) => notes.clone(),
+			other => other.port_changes()
+		};
+		let numbers = |filter : fn(&Change<Port>) -> bool| changes.iter().filter(|x| filter(x)).map(|x| x.latest().port_number).collect::<Vec<u16>>();
+		(numbers(|x| !x.is_informational()), numbers(|x| matches!(x, Change::Collapsed(_))), numbers(|x| matches!(x, Change::Expanded(_))))
+	}
+	
+	#[test]
+	fn reports_ports_collapsed_into_extraports() {
+		let left = scan(&host(&["10.0.0.1"], None, &[extraports("closed", 997), port(22, "open"), port(23, "closed"), port(25, "closed")].concat()));
+		let right = scan(&host(&["10.0.0.1"], None, &[extraports("closed", 999), port(22, "open")].concat()));
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Unchanged]);
+		assert_eq!(port_summary(&deltas[0]), (vec![], vec![23, 25], vec![]));
+	}
+	
+	#[test]
+	fn reports_ports_expanded_from_extraports() {
+		let left = scan(&host(&["10.0.0.1"], None, &[extraports("filtered", 999), port(22, "open")].concat()));
+		let right = scan(&host(&["10.0.0.1"], None, &[extraports("filtered", 998), port(22, "open"), port(443, "filtered")].concat()));
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Unchanged]);
+		assert_eq!(port_summary(&deltas[0]), (vec![], vec![], vec![443]));
+	}
+	
+	#[test]
+	fn reports_ports_removed_when_extraports_did_not_grow_enough() {
+		// Two closed ports disappeared, but the closed extraports count only grew by one, so they can't both have been collapsed.
+		let left = scan(&host(&["10.0.0.1"], None, &[extraports("closed", 997), port(22, "open"), port(23, "closed"), port(25, "closed")].concat()));
+		let right = scan(&host(&["10.0.0.1"], None, &[extraports("closed", 998), port(22, "open")].concat()));
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed]);
+		assert_eq!(port_summary(&deltas[0]), (vec![23, 25], vec![], vec![]));
+	}
+	
+	#[test]
+	fn reports_ports_removed_in_a_different_state_from_extraports() {
+		// The closed extraports count grew, but the port that disappeared was open.
+		let left = scan(&host(&["10.0.0.1"], None, &[extraports("closed", 998), port(22, "open"), port(80, "open")].concat()));
+		let right = scan(&host(&["10.0.0.1"], None, &[extraports("closed", 999), port(22, "open")].concat()));
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(port_summary(&deltas[0]), (vec![80], vec![], vec![]));
+		assert_eq!(deltas[0].closed_ports().iter().map(|x| x.port_number).collect::<Vec<u16>>(), vec![80]);
+	}
+	
+	#[test]
+	fn reports_ports_added_without_extraports_on_the_left() {
+		let left = scan(&host(&["10.0.0.1"], None, &port(22, "open")));
+		let right = scan(&host(&["10.0.0.1"], None, &[extraports("closed", 998), port(22, "open"), port(23, "closed")].concat()));
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed]);
+		assert_eq!(port_summary(&deltas[0]), (vec![23], vec![], vec![]));
+	}
+	
+	/// Diffs a scan of ports 1-1000 against a scan of every port, where 8080 was open, and 23 closed on the left only.
+	fn coverage_deltas(coverage : CoveragePolicy) -> Vec<HostDelta> {
+		let left = scan_covering("1-1000", &host(&["10.0.0.1"], None, &[port(22, "open"), port(23, "closed")].concat()));
+		let right = scan_covering("1-65535", &host(&["10.0.0.1"], None, &[port(22, "open"), port(8080, "open")].concat()));
+		HostDelta::from_scans(&left, &right, &DiffOptions { coverage, ..Default::default() })
+	}
+	
+	#[test]
+	fn flags_ports_only_one_scan_covered() {
+		let deltas = coverage_deltas(CoveragePolicy::Flag);
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed]);
+		let changes = deltas[0].port_changes();
+		assert!(changes.iter().any(|x| matches!(x, Change::OnlyScannedRight(port) if port.port_number == 8080)), "{:?}", changes);
+		assert!(changes.iter().any(|x| matches!(x, Change::Removed(port) if port.port_number == 23)), "{:?}", changes);
+		// An open port that wasn't seen before is still a new exposure, even if the left scan didn't look for it.
+		assert_eq!(deltas[0].opened_ports().iter().map(|x| x.port_number).collect::<Vec<u16>>(), vec![8080]);
+		assert!(deltas[0].to_string().contains("| Not covered by the left scan: tcp 8080\n"), "{}", deltas[0]);
+	}
+	
+	#[test]
+	fn suppresses_ports_only_one_scan_covered() {
+		let deltas = coverage_deltas(CoveragePolicy::Suppress);
+		let changes : Vec<u16> = deltas[0].port_changes().iter().filter(|x| !x.is_informational()).map(|x| x.latest().port_number).collect();
+		assert_eq!(changes, vec![23]);
+		assert!(deltas[0].opened_ports().is_empty());
+	}
+	
+	#[test]
+	fn reports_ports_only_one_scan_covered_as_added_when_ignoring_coverage() {
+		let deltas = coverage_deltas(CoveragePolicy::Ignore);
+		let changes = deltas[0].port_changes();
+		assert!(changes.iter().any(|x| matches!(x, Change::Added(port) if port.port_number == 8080)), "{:?}", changes);
+		assert!(!deltas[0].to_string().contains("Not covered"), "{}", deltas[0]);
+	}
+	
+	#[test]
+	fn treats_scans_without_scaninfo_as_covering_every_port() {
+		let left = Scan::parse(&nmaprun("nmap 10.0.0.1", 1700000000, &host(&["10.0.0.1"], None, &port(22, "open")))).unwrap();
+		let right = scan_covering("1-65535", &host(&["10.0.0.1"], None, &[port(22, "open"), port(8080, "open")].concat()));
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(deltas[0].opened_ports().iter().map(|x| x.port_number).collect::<Vec<u16>>(), vec![8080]);
+	}
+	
+	#[test]
+	fn pairs_hosts_by_address_regardless_of_order() {
+		let left = scan(&[host(&["10.0.0.1"], None, &port(22, "open")), host(&["10.0.0.2"], None, &port(80, "open"))].concat());
+		let right = scan(&[host(&["10.0.0.2"], None, &port(80, "open")), host(&["10.0.0.1"], None, &port(22, "closed"))].concat());
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed, DeltaKind::Unchanged]);
+		let changed = deltas.iter().find(|x| x.kind() == DeltaKind::Changed).unwrap();
+		assert_eq!(AddressesWrapper(changed.host().addresses().cloned().collect()).entries(), vec!["10.0.0.1"]);
+	}
+	
+	#[test]
+	fn pairs_each_old_host_once() {
+		// Both new hosts share an address with the one old host, so only the first of them can be paired with it.
+		let left = scan(&host(&["10.0.0.1", "10.0.0.2"], None, ""));
+		let right = scan(&[host(&["10.0.0.1"], None, ""), host(&["10.0.0.2"], None, "")].concat());
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed, DeltaKind::New]);
+		let new = deltas.iter().find(|x| x.kind() == DeltaKind::New).unwrap();
+		assert_eq!(AddressesWrapper(new.host().addresses().cloned().collect()).entries(), vec!["10.0.0.2"]);
+	}
+	
+	#[test]
+	fn pairs_dual_stack_hosts_by_hostname() {
+		let left = scan(&host(&["10.0.0.1"], Some("gw.example"), &port(22, "open")));
+		let right = scan(&host(&["2001:db8::1"], Some("GW.example"), &port(22, "open")));
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed]);
+		match &deltas[0] {
+			HostDelta::Changed(diff) => assert_eq!(diff.title, "GW.example (10.0.0.1, 2001:db8::1)"),
+			_ => unreachable!()
+		}
+	}
+	
+	#[test]
+	fn reports_hostnames_and_addresses_that_were_gained_or_lost() {
+		let changed = |left : &str, right : &str| {
+			let deltas = HostDelta::from_scans(&scan(left), &scan(right), &DiffOptions::default());
+			match &deltas[0] {
+				HostDelta::Changed(diff) => (diff.hostnames.is_some(), diff.addresses.is_some()),
+				_ => (false, false)
+			}
+		};
+		let bare = host(&["10.0.0.1"], None, "");
+		let named = host(&["10.0.0.1"], Some("gw.example"), "");
+		let with_mac = host(&["10.0.0.1", "00:11:22:33:44:55"], Some("gw.example"), "");
+		
+		assert_eq!(changed(&bare, &named), (true, false));
+		assert_eq!(changed(&named, &bare), (true, false));
+		assert_eq!(changed(&named, &with_mac), (false, true));
+		assert_eq!(changed(&with_mac, &named), (false, true));
+		// Hostnames are compared without regard to case.
+		assert_eq!(changed(&named, &host(&["10.0.0.1"], Some("GW.Example"), "")), (false, false));
+		assert_eq!(kinds(&HostDelta::from_scans(&scan(&named), &scan(&host(&["10.0.0.1"], Some("GW.Example"), "")), &DiffOptions::default())), vec![DeltaKind::Unchanged]);
+	}
+	
+	#[test]
+	fn does_not_pair_hostnames_within_the_same_family() {
+		// The name now points at a different IPv4 address, which may well be a different machine.
+		let left = scan(&host(&["10.0.0.1"], Some("gw.example"), ""));
+		let right = scan(&host(&["10.0.0.9"], Some("gw.example"), ""));
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Gone, DeltaKind::New]);
+	}
+	
+	#[test]
+	fn does_not_pair_hostnames_of_hosts_matched_by_address() {
+		// The old IPv4 host still shares an address with a new host, so it isn't free to be paired with the IPv6 one by name.
+		let left = scan(&host(&["10.0.0.1"], Some("gw.example"), ""));
+		let right = scan(&[host(&["10.0.0.1"], None, ""), host(&["2001:db8::1"], Some("gw.example"), "")].concat());
+		
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed, DeltaKind::New]);
+	}
+	
+	/// Diffs a host against itself with different extra elements on each side, such as `<os>`, returning the diff if the host
+	/// changed.
+	fn diff_extras(left : &str, right : &str, options : &DiffOptions) -> Option<HostDiff> {
+		let left = scan(&host_with(&["10.0.0.1"], None, &port(22, "open"), left));
+		let right = scan(&host_with(&["10.0.0.1"], None, &port(22, "open"), right));
+		match HostDelta::from_scans(&left, &right, options).remove(0) {
+			HostDelta::Changed(diff) => Some(*diff),
+			_ => None
+		}
+	}
+	
+	fn os(name : &str, generation : &str, accuracy : u8) -> String {
+		format!(r#"<os><osmatch name="{}" accuracy="{}" line="1"><osclass type="general purpose" vendor="Linux" osfamily="Linux" osgen="{}" accuracy="{}"/></osmatch></os>"#, name, accuracy, generation, accuracy)
+	}
+	
+	#[test]
+	fn reports_os_guesses_that_changed() {
+		let options = DiffOptions::default();
+		let os_change = |left : &str, right : &str, options : &DiffOptions| diff_extras(left, right, options).and_then(|x| x.os).map(|(left, right)| (left.accuracy, right.accuracy));
+		
+		assert_eq!(os_change(&os("Linux 5.0 - 5.14", "5.X", 96), &os("Linux 6.1", "6.X", 96), &options), Some((96, 96)));
+		// The class alone is enough, even if the name stays the same.
+		assert_eq!(os_change(&os("Linux", "5.X", 96), &os("Linux", "6.X", 96), &options), Some((96, 96)));
+		// Accuracy has to move by more than the threshold.
+		assert_eq!(os_change(&os("Linux 6.1", "6.X", 96), &os("Linux 6.1", "6.X", 91), &options), None);
+		assert_eq!(os_change(&os("Linux 6.1", "6.X", 96), &os("Linux 6.1", "6.X", 90), &options), Some((96, 90)));
+		assert_eq!(os_change(&os("Linux 6.1", "6.X", 96), &os("Linux 6.1", "6.X", 91), &DiffOptions { os_accuracy_threshold: 4, ..Default::default() }), Some((96, 91)));
+		// A guess on only one side isn't a change, since OS detection often fails.
+		assert!(diff_extras(&os("Linux 6.1", "6.X", 96), "", &options).is_none());
+		assert!(diff_extras("", &os("Linux 6.1", "6.X", 96), &options).is_none());
+	}
+	
+	/// An uptime element for a host that booted this many seconds before the fixture's host finished scanning.
+	fn uptime(seconds : i64) -> String {
+		format!(r#"<uptime seconds="{}" lastboot="Tue Nov 14 22:13:20 2023"/>"#, seconds)
+	}
+	
+	#[test]
+	fn reports_reboots_beyond_the_tolerance() {
+		let options = DiffOptions::default();
+		let reboot = |left : &str, right : &str, options : &DiffOptions| diff_extras(left, right, options).and_then(|x| x.reboot);
+		let end_time = 1700000002;
+		
+		assert_eq!(reboot(&uptime(86400), &uptime(3600), &options), Some((end_time - 86400, end_time - 3600)));
+		// Nmap's guesses drift a little between scans, so the boot time has to move by more than the tolerance.
+		assert_eq!(reboot(&uptime(86400), &uptime(86100), &options), None);
+		assert_eq!(reboot(&uptime(86400), &uptime(86099), &options), Some((end_time - 86400, end_time - 86099)));
+		assert_eq!(reboot(&uptime(86400), &uptime(86100), &DiffOptions { reboot_tolerance: 100, ..Default::default() }), Some((end_time - 86400, end_time - 86100)));
+		// A boot time that moved backwards is drift rather than a reboot.
+		assert_eq!(reboot(&uptime(3600), &uptime(86400), &options), None);
+		assert_eq!(reboot(&uptime(86400), "", &options), None);
+		assert_eq!(reboot("", &uptime(3600), &options), None);
+	}
+	
+	/// A distance and a traceroute through the given hops, along with the times Nmap measured for them.
+	fn route(distance : u32, hops : &[(&str, f64)]) -> String {
+		let hops : String = hops.iter().enumerate().map(|(ttl, (address, rtt))| format!(r#"<hop ttl="{}" ipaddr="{}" rtt="{}"/>"#, ttl + 1, address, rtt)).collect();
+		format!(r#"<distance value="{}"/><trace port="22" proto="tcp">{}</trace>"#, distance, hops)
+	}
+	
+	#[test]
+	fn reports_route_changes_when_asked_to() {
+		let options = DiffOptions { compare_routes: true, ..Default::default() };
+		let route_change = |left : &str, right : &str, options : &DiffOptions| diff_extras(left, right, options).and_then(|x| x.route).map(|(left, right)| (left.to_string(), right.to_string()));
+		let direct = route(2, &[("10.0.0.254", 0.5), ("10.0.0.1", 1.0)]);
+		
+		assert_eq!(route_change(&direct, &route(3, &[("10.0.0.254", 0.5), ("10.0.9.254", 0.7), ("10.0.0.1", 1.2)]), &options).map(|x| x.1), Some("3 hops via 10.0.0.254 -> 10.0.9.254 -> 10.0.0.1".to_string()));
+		assert!(route_change(&direct, &route(2, &[("10.0.0.253", 0.5), ("10.0.0.1", 1.0)]), &options).is_some());
+		// Round trip times vary from scan to scan, so they don't count.
+		assert_eq!(route_change(&direct, &route(2, &[("10.0.0.254", 3.5), ("10.0.0.1", 9.0)]), &options), None);
+		// Routes are only compared when asked for, and when both sides have one.
+		assert_eq!(route_change(&direct, &route(3, &[("10.0.0.253", 0.5)]), &DiffOptions::default()), None);
+		assert_eq!(route_change(&direct, "", &options), None);
+		assert_eq!(route_change("", &direct, &options), None);
+		assert_eq!(route_change(&direct, r#"<distance value="3"/>"#, &options).map(|x| x.1), Some("3 hops".to_string()));
+	}
+	
+	#[test]
+	fn reports_down_hosts_according_to_the_policy() {
+		let down = |address : &str| format!(r#"<host><status state="down" reason="no-response" reason_ttl="0"/><address addr="{}" addrtype="ipv4"/></host>"#, address);
+		// 10.0.0.1 goes down, 10.0.0.2 was down and is missing, and 10.0.0.3 is down and wasn't there before.
+		let left = scan(&format!("{}{}", host(&["10.0.0.1"], None, &open(&[22])), down("10.0.0.2")));
+		let right = scan(&format!("{}{}", down("10.0.0.1"), down("10.0.0.3")));
+		let deltas = |down_hosts : DownHostPolicy| {
+			let mut deltas : Vec<(DeltaKind,Vec<String>)> = HostDelta::from_scans(&left, &right, &DiffOptions { down_hosts, ..Default::default() }).iter()
+				.map(|x| (x.kind(), AddressesWrapper(x.host().addresses().cloned().collect()).sorted_ips()))
+				.collect();
+			deltas.sort();
+			deltas
+		};
+		
+		assert_eq!(deltas(DownHostPolicy::Present), vec![
+			(DeltaKind::Changed, vec!["10.0.0.1".to_string()]),
+			(DeltaKind::Gone, vec!["10.0.0.2".to_string()]),
+			(DeltaKind::New, vec!["10.0.0.3".to_string()])
+		]);
+		assert_eq!(deltas(DownHostPolicy::Absent), vec![(DeltaKind::Gone, vec!["10.0.0.1".to_string()])]);
+		
+		match HostDelta::from_scans(&left, &right, &DiffOptions::default()).into_iter().find(|x| x.kind() == DeltaKind::Changed) {
+			Some(HostDelta::Changed(diff)) => assert_eq!(diff.status.map(|(left, right)| (left.state, right.state)), Some((HostState::Up, HostState::Down))),
+			_ => panic!("10.0.0.1 should have changed")
+		}
+	}
+}
@@ -0,0 +1,328 @@
+use std::fmt;
+
+use chrono::NaiveDate;
+use nmap_xml_parser::host::Host;
+use nmap_xml_parser::port::Port;
+
+use crate::host::DeltaKind;
+use crate::host::HostDelta;
+use crate::rulefile;
+use crate::rulefile::PortFilter;
+use crate::scope::Scope;
+use crate::scope::parse_list;
+
+/// A single line of an ignore file, e.g. "hostname=*.dhcp.example kind=new,gone expires=2025-06-30 reason=DHCP pool". Entries
+/// with port or service conditions suppress individual port changes, and other entries suppress whole hosts.
+#[derive(Debug,Clone)]
+pub struct IgnoreEntry {
+	pub line: usize,
+	pub scope: Scope,
+	pub hostname: Option<String>,
+	pub kinds: Vec<DeltaKind>,
+	pub filter: PortFilter,
+	pub expires: Option<NaiveDate>,
+	pub reason: Option<String>
+}
+
+/// Known, accepted changes which shouldn't be reported, such as a load balancer rotating its backends.
+#[derive(Debug,Clone,Default)]
+pub struct IgnoreList {
+	pub entries: Vec<IgnoreEntry>
+}
+
+/// What an ignore list removed from a diff, so that suppressed changes are never completely invisible.
+#[derive(Debug,Clone,Default)]
+pub struct Suppressed {
+	pub hosts: usize,
+	pub ports: usize,
+	/// Warnings about the entries which have expired, and so weren't applied.
+	pub expired: Vec<String>
+}
+
+impl IgnoreList {
+	pub fn load(path : &str) -> Result<IgnoreList,String> {
+		rulefile::load(path, IgnoreList::parse)
+	}
+	
+	/// Parses an ignore file, with one entry per line. Anything after a '#' is a comment, and anything after "reason=" is the
+	/// reason for the entry.
+	pub fn parse(content : &str) -> Result<IgnoreList,String> {
+		let mut entries : Vec<IgnoreEntry> = Vec::new();
+		rulefile::parse_lines(content, |line, number| IgnoreEntry::parse(line, number).map(|x| entries.push(x)))?;
+		Ok(IgnoreList { entries })
+	}
+	
+	/// Checks whether any entry that hasn't expired by `today` accepts a port on a host, either on its own or as part of the
+	/// whole host.
+	pub fn accepts(&self, delta : &HostDelta, port : &Port, today : NaiveDate) -> bool {
+		self.entries.iter()
+			.filter(|x| x.expires.is_none_or(|date| date >= today))
+			.any(|x| x.matches_host(delta) && (!x.port_specific() || x.filter.matches(port)))
+	}
+	
+	/// Removes the hosts and port changes matched by any entry that hasn't expired by `today`. Changed hosts which only had
+	/// suppressed changes are reported as unchanged, and new or gone hosts whose ports were all suppressed are left out.
+	pub fn apply(&self, deltas : Vec<HostDelta>, today : NaiveDate) -> (Vec<HostDelta>, Suppressed) {
+		let mut suppressed = Suppressed::default();
+		let mut active : Vec<&IgnoreEntry> = Vec::new();
+		for entry in &self.entries {
+			match entry.expires {
+				Some(date) if date < today => suppressed.expired.push(format!("ignore entry on {} expired on {}", entry, date)),
+				_ => active.push(entry)
+			}
+		}
+		
+		let mut output : Vec<HostDelta> = Vec::new();
+		for delta in deltas {
+			if delta.kind() == DeltaKind::Unchanged {
+				output.push(delta);
+				continue;
+			}
+			
+			let matching : Vec<&&IgnoreEntry> = active.iter().filter(|x| x.matches_host(&delta)).collect();
+			if matching.iter().any(|x| !x.port_specific()) {
+				suppressed.hosts += 1;
+				continue;
+			}
+			
+			match delta {
+				HostDelta::Changed(mut diff) if !matching.is_empty() => {
+					suppressed.ports += diff.suppress_ports(|change| matching.iter().any(|x| x.filter.matches(change.latest())));
+					output.push(diff.into_delta());
+				},
+				// A new or gone host can't lose individual ports, so it's only suppressed if every one of its ports is.
+				HostDelta::New(_) | HostDelta::Gone(_) if !matching.is_empty() => {
+					let changes = delta.port_changes();
+					match !changes.is_empty() && changes.iter().all(|change| matching.iter().any(|x| x.filter.matches(change.latest()))) {
+						true => {
+							suppressed.hosts += 1;
+							suppressed.ports += changes.len();
+						},
+						false => output.push(delta)
+					};
+				},
+				other => output.push(other)
+			}
+		}
+		
+		(output, suppressed)
+	}
+}
+
+impl IgnoreEntry {
+	fn parse(line : &str, number : usize) -> Result<IgnoreEntry,String> {
+		let (conditions, reason) = match line.split_once("reason=") {
+			Some((conditions, reason)) => (conditions, Some(reason.trim().to_string())),
+			None => (line, None)
+		};
+		
+		let mut entry = IgnoreEntry { line: number, scope: Scope::default(), hostname: None, kinds: Vec::new(), filter: PortFilter::default(), expires: None, reason };
+		let mut has_condition = false;
+		for condition in conditions.split_whitespace() {
+			has_condition |= !condition.starts_with("expires=");
+			if entry.filter.parse_condition(condition)? { continue; }
+			match condition.split_once('=') {
+				Some(("address", x)) => entry.scope.include = parse_list(x)?,
+				Some(("hostname", x)) => entry.hostname = Some(x.to_lowercase()),
+				Some(("kind", x)) => entry.kinds = x.split(',').map(parse_kind).collect::<Result<Vec<DeltaKind>,String>>()?,
+				Some(("expires", x)) => entry.expires = Some(NaiveDate::parse_from_str(x, "%Y-%m-%d").map_err(|_| format!("invalid date '{}' (expected YYYY-MM-DD)", x))?),
+				_ => return Err(format!("unknown condition '{}' (expected address=, hostname=, kind=, proto=, port=, service=, expires= or reason=)", condition))
+			}
+		}
+		
+		// An entry without any conditions would suppress everything, which is never what was meant.
+		match has_condition {
+			true => Ok(entry),
+			false => Err("entry has no conditions".to_string())
+		}
+	}
+	
+	fn port_specific(&self) -> bool {
+		!self.filter.is_empty()
+	}
+	
+	fn matches_host(&self, delta : &HostDelta) -> bool {
+		let (left, right) = delta.sides();
+		let hosts : Vec<&Host> = left.into_iter().chain(right).collect();
+		let hostname_matches = |pattern : &String| hosts.iter().any(|host| host.host_names().any(|x| glob_matches(pattern, &x.name.to_lowercase())));
+		
+		hosts.iter().any(|x| self.scope.contains(x))
+			&& self.hostname.as_ref().is_none_or(hostname_matches)
+			&& (self.kinds.is_empty() || self.kinds.contains(&delta.kind()))
+	}
+}
+
+fn parse_kind(kind : &str) -> Result<DeltaKind,String> {
+	match kind {
+		"changed" => Ok(DeltaKind::Changed),
+		"gone" => Ok(DeltaKind::Gone),
+		"new" => Ok(DeltaKind::New),
+		_ => Err(format!("unknown kind '{}' (expected changed, gone or new)", kind))
+	}
+}
+
+/// Matches a hostname against a pattern where '*' matches any number of characters and '?' matches exactly one.
+fn glob_matches(pattern : &str, text : &str) -> bool {
+	let pattern : Vec<char> = pattern.chars().collect();
+	let text : Vec<char> = text.chars().collect();
+	
+	// Classic backtracking match: remember the last '*' and retry from one character further along when a match fails.
+	let (mut p, mut t) = (0, 0);
+	let mut star : Option<(usize, usize)> = None;
+	while t < text.len() {
+		match pattern.get(p) {
+			Some('*') => { star = Some((p, t)); p += 1; },
+			Some(x) if *x == '?' || *x == text[t] => { p += 1; t += 1; },
+			_ => match star {
+				Some((star_p, star_t)) => { p = star_p + 1; t = star_t + 1; star = Some((star_p, star_t + 1)); },
+				None => return false
+			}
+		}
+	}
+	
+	pattern[p..].iter().all(|x| *x == '*')
+}
+
+impl fmt::Display for IgnoreEntry {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.reason {
+			Some(reason) => write!(f, "line {} ({})", self.line, reason),
+			None => write!(f, "line {}", self.line)
+		}
+	}
+}
+
+impl fmt::Display for Suppressed {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "[=] Suppressed: {} hosts, {} port changes", self.hosts, self.ports)?;
+		for warning in &self.expired {
+			writeln!(f, "Warning: {}", warning)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	use crate::host::DiffOptions;
+	
+	/// Diffs a scan where the gateway had SSH open against one where it also has 8080 and 8443 open, and a DHCP client appeared.
+	fn diff() -> Vec<HostDelta> {
+		let left = scan(&host(&["10.0.0.1"], Some("gw.example"), &open(&[22])));
+		let right = scan(&[host(&["10.0.0.1"], Some("gw.example"), &open(&[22, 8080, 8443])), host(&["10.0.0.50"], Some("host-50.DHCP.example"), &open(&[135]))].concat());
+		HostDelta::from_scans(&left, &right, &DiffOptions::default())
+	}
+	
+	fn date(s : &str) -> NaiveDate {
+		NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+	}
+	
+	fn kinds(deltas : &[HostDelta]) -> Vec<DeltaKind> {
+		deltas.iter().map(|x| x.kind()).collect()
+	}
+	
+	#[test]
+	fn matches_hostname_patterns() {
+		assert!(glob_matches("*.dhcp.example", "host-50.dhcp.example"));
+		assert!(glob_matches("*.dhcp.example", ".dhcp.example"));
+		assert!(!glob_matches("*.dhcp.example", "dhcp.example"));
+		assert!(glob_matches("web-??.example", "web-01.example"));
+		assert!(!glob_matches("web-??.example", "web-1.example"));
+		assert!(glob_matches("a*b*c", "axxbyyc"));
+		assert!(!glob_matches("a*b*c", "axxbyycx"));
+		// A '*' that first matches too little has to be retried further along.
+		assert!(glob_matches("*x", "xyx"));
+		assert!(glob_matches("*", ""));
+		assert!(glob_matches("", ""));
+		assert!(!glob_matches("", "a"));
+		assert!(!glob_matches("gw.example", "gw.example.org"));
+	}
+	
+	#[test]
+	fn parses_entries() {
+		let list = IgnoreList::parse("# Accepted changes\n\nhostname=*.dhcp.example kind=new,gone expires=2025-06-30 reason=DHCP pool # rotates\nproto=tcp port=8000-8999 address=10.0.0.0/24\n").unwrap();
+		assert_eq!(list.entries.len(), 2);
+		
+		let entry = &list.entries[0];
+		assert_eq!(entry.line, 3);
+		assert_eq!(entry.hostname.as_deref(), Some("*.dhcp.example"));
+		assert_eq!(entry.kinds, vec![DeltaKind::New, DeltaKind::Gone]);
+		assert_eq!(entry.expires, Some(date("2025-06-30")));
+		assert_eq!(entry.reason.as_deref(), Some("DHCP pool"));
+		assert!(!entry.port_specific());
+		assert!(list.entries[1].port_specific());
+		assert_eq!(list.entries[1].filter.ports, vec![(8000, 8999)]);
+	}
+	
+	#[test]
+	fn rejects_invalid_entries() {
+		let error = |content : &str| IgnoreList::parse(content).unwrap_err();
+		assert_eq!(error("reason=everything"), "entry has no conditions on line 1");
+		assert_eq!(error("port=22\nexpires=2025-06-30"), "entry has no conditions on line 2");
+		assert_eq!(error("port=22 expires=30/06/2025"), "invalid date '30/06/2025' (expected YYYY-MM-DD) on line 1");
+		assert_eq!(error("kind=unchanged"), "unknown kind 'unchanged' (expected changed, gone or new) on line 1");
+		assert!(error("host=gw.example").starts_with("unknown condition 'host=gw.example'"));
+	}
+	
+	#[test]
+	fn suppresses_whole_hosts() {
+		let list = IgnoreList::parse("hostname=*.dhcp.example kind=new").unwrap();
+		let (deltas, suppressed) = list.apply(diff(), date("2025-01-01"));
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed]);
+		assert_eq!((suppressed.hosts, suppressed.ports), (1, 0));
+		
+		// The kind has to match as well as the hostname.
+		let list = IgnoreList::parse("hostname=*.dhcp.example kind=gone").unwrap();
+		let (deltas, suppressed) = list.apply(diff(), date("2025-01-01"));
+		assert_eq!(kinds(&deltas), vec![DeltaKind::New, DeltaKind::Changed]);
+		assert_eq!(suppressed.hosts, 0);
+	}
+	
+	#[test]
+	fn suppresses_port_changes() {
+		// With only some of its changes suppressed, the gateway is still changed.
+		let list = IgnoreList::parse("address=10.0.0.1 port=8080").unwrap();
+		let (deltas, suppressed) = list.apply(diff(), date("2025-01-01"));
+		assert_eq!(kinds(&deltas), vec![DeltaKind::New, DeltaKind::Changed]);
+		assert_eq!(deltas[1].opened_ports().iter().map(|x| x.port_number).collect::<Vec<u16>>(), vec![8443]);
+		assert_eq!((suppressed.hosts, suppressed.ports), (0, 1));
+		
+		// With all of them suppressed, it's unchanged.
+		let list = IgnoreList::parse("address=10.0.0.1 port=8000-8999").unwrap();
+		let (deltas, suppressed) = list.apply(diff(), date("2025-01-01"));
+		assert_eq!(kinds(&deltas), vec![DeltaKind::New, DeltaKind::Unchanged]);
+		assert_eq!((suppressed.hosts, suppressed.ports), (0, 2));
+	}
+	
+	#[test]
+	fn only_suppresses_new_hosts_when_every_port_is_suppressed() {
+		let list = IgnoreList::parse("port=135\nport=8080").unwrap();
+		let (deltas, suppressed) = list.apply(diff(), date("2025-01-01"));
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed]);
+		assert_eq!((suppressed.hosts, suppressed.ports), (1, 2));
+		
+		let list = IgnoreList::parse("proto=udp port=135").unwrap();
+		let (deltas, _) = list.apply(diff(), date("2025-01-01"));
+		assert_eq!(kinds(&deltas), vec![DeltaKind::New, DeltaKind::Changed]);
+	}
+	
+	#[test]
+	fn stops_applying_entries_after_they_expire() {
+		let list = IgnoreList::parse("hostname=*.dhcp.example expires=2025-06-30 reason=DHCP pool").unwrap();
+		
+		// Entries still apply on the day they expire.
+		let (deltas, suppressed) = list.apply(diff(), date("2025-06-30"));
+		assert_eq!(kinds(&deltas), vec![DeltaKind::Changed]);
+		assert!(suppressed.expired.is_empty());
+		let new = &diff()[0];
+		assert!(list.accepts(new, new.host().port_info.ports().next().unwrap(), date("2025-06-30")));
+		
+		let (deltas, suppressed) = list.apply(diff(), date("2025-07-01"));
+		assert_eq!(kinds(&deltas), vec![DeltaKind::New, DeltaKind::Changed]);
+		assert_eq!(suppressed.hosts, 0);
+		assert_eq!(suppressed.expired, vec!["ignore entry on line 1 (DHCP pool) expired on 2025-06-30"]);
+		assert!(!list.accepts(new, new.host().port_info.ports().next().unwrap(), date("2025-07-01")));
+	}
+}
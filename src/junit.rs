@@ -0,0 +1,193 @@
+use chrono::DateTime;
+
+use crate::host::HostDelta;
+use crate::host::HostWrapper;
+use crate::policy::PolicyReport;
+use crate::scan::Scan;
+use crate::severity::Severity;
+use crate::severity::SeverityRules;
+
+/// Builds a JUnit XML report from the deltas between two scans, with one test case per host. Unchanged hosts pass, and hosts
+/// which changed, appeared or disappeared fail with their diff as the failure text, followed by their severities if there are
+/// rules. Like the text report, only hosts at least as severe as `min_severity` are included. Each policy rule is a test case
+/// as well.
+pub fn to_junit(left_path : &str, right_path : &str, right : &Scan, deltas : &[HostDelta], rules : Option<&SeverityRules>, min_severity : Option<Severity>, policy : Option<&PolicyReport>) -> String {
+	let suite_name = format!("{} => {}", left_path, right_path);
+	// JUnit timestamps are ISO 8601, without a timezone.
+	let timestamp = match DateTime::from_timestamp(right.results.scan_start_time, 0) {
+		Some(x) => x.format("%Y-%m-%dT%H:%M:%S").to_string(),
+		None => String::new()
+	};
+	let mut suites : Vec<String> = Vec::new();
+	
+	let mut cases : Vec<String> = Vec::new();
+	let mut failures = 0;
+	for delta in deltas {
+		let assessment = rules.and_then(|x| x.assess(delta));
+		if min_severity.is_some_and(|min| assessment.as_ref().is_none_or(|x| x.severity < min)) { continue; }
+		
+		let (name, failure) = match delta {
+			HostDelta::Unchanged(host, _) => (HostWrapper(host.clone()).get_title(), None),
+			HostDelta::Changed(diff) => (diff.title.clone(), Some(("Changed", delta.to_string()))),
+			HostDelta::New(host) => (HostWrapper(host.clone()).get_title(), Some(("New", delta.to_string()))),
+			// The text for a gone host is just its title, so the host itself is included to show what went missing.
+			HostDelta::Gone(host) => (HostWrapper(host.clone()).get_title(), Some(("Gone", format!("{}\n{}", delta.to_string().trim_end(), HostWrapper(host.clone())))))
+		};
+		let failure = failure.map(|(message, text)| match &assessment {
+			Some(x) => (message, format!("{}\n{}", text.trim_end(), x)),
+			None => (message, text)
+		});
+		if failure.is_some() { failures += 1; }
+		cases.push(test_case(&name, "hosts", failure));
+	}
+	suites.push(test_suite(&format!("hosts: {}", suite_name), &timestamp, failures, cases));
+	
+	if let Some(policy) = policy {
+		let mut cases : Vec<String> = Vec::new();
+		let mut failures = 0;
+		for (rule, violations) in &policy.results {
+			let failure = match violations.is_empty() {
+				true => None,
+				false => {
+					failures += 1;
+					let text = violations.iter().map(|x| x.to_string()).collect::<Vec<String>>().join("\n");
+					Some(("Policy violated", text))
+				}
+			};
+			cases.push(test_case(rule, "policy", failure));
+		}
+		suites.push(test_suite(&format!("policy: {}", suite_name), &timestamp, failures, cases));
+	}
+	
+	format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"ndiff-rs\">\n{}</testsuites>\n", suites.concat())
+}
+
+fn test_suite(name : &str, timestamp : &str, failures : usize, cases : Vec<String>) -> String {
+	format!(
+		"  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"0\" timestamp=\"{}\">\n{}  </testsuite>\n",
+		xml_escape(name), cases.len(), failures, xml_escape(timestamp), cases.concat()
+	)
+}
+
+fn test_case(name : &str, classname : &str, failure : Option<(&str, String)>) -> String {
+	match failure {
+		Some((message, text)) => format!(
+			"    <testcase name=\"{}\" classname=\"{}\">\n      <failure message=\"{}\" type=\"{}\">{}</failure>\n    </testcase>\n",
+			xml_escape(name), classname, xml_escape(message), xml_escape(message), xml_escape(text.trim_end())
+		),
+		None => format!("    <testcase name=\"{}\" classname=\"{}\"/>\n", xml_escape(name), classname)
+	}
+}
+
+/// Escapes text for use in XML attributes and element content.
+pub fn xml_escape(value : &str) -> String {
+	let mut output = String::new();
+	for c in value.chars() {
+		match c {
+			'&' => output.push_str("&amp;"),
+			'<' => output.push_str("&lt;"),
+			'>' => output.push_str("&gt;"),
+			'"' => output.push_str("&quot;"),
+			'\'' => output.push_str("&apos;"),
+			c => output.push(c)
+		}
+	}
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use roxmltree::Document;
+	
+	use super::*;
+	use crate::fixtures::*;
+	use crate::host::DiffOptions;
+	use crate::policy::Policy;
+	
+	/// The gateway, whose name and telnet service need escaping, opened telnet. 10.0.0.2 went away, 10.0.0.4 appeared with
+	/// 8080 open and 10.0.0.3 didn't change.
+	fn scans() -> (Scan, Scan) {
+		let gateway = "o&apos;brien &amp; co &lt;lab&gt;.example";
+		let left = scan(&[
+			host(&["10.0.0.1"], Some(gateway), &open(&[22])),
+			host(&["10.0.0.2"], None, &open(&[80])),
+			host(&["10.0.0.3"], None, &open(&[22]))
+		].concat());
+		let right = scan(&[
+			host(&["10.0.0.1"], Some(gateway), &[port(22, "open"), service(23, "telnet&quot;&amp;", "Linux telnetd", "")].concat()),
+			host(&["10.0.0.3"], None, &open(&[22])),
+			host(&["10.0.0.4"], None, &open(&[8080]))
+		].concat());
+		(left, right)
+	}
+	
+	/// A test suite parsed back from a report, with each test case's name along with its failure text, if it failed.
+	struct Suite {
+		name: String,
+		tests: usize,
+		failures: usize,
+		cases: Vec<(String, Option<String>)>
+	}
+	
+	fn parse(report : &str) -> Vec<Suite> {
+		let doc = Document::parse(report).unwrap();
+		doc.root_element().children().filter(|x| x.has_tag_name("testsuite")).map(|suite| {
+			let count = |name : &str| suite.attribute(name).unwrap().parse::<usize>().unwrap();
+			let cases : Vec<(String, Option<String>)> = suite.children().filter(|x| x.has_tag_name("testcase")).map(|case| {
+				let failure = case.children().find(|x| x.has_tag_name("failure")).map(|x| x.text().unwrap_or_default().to_string());
+				(case.attribute("name").unwrap().to_string(), failure)
+			}).collect();
+			// The counts have to agree with the test cases, or CI systems will report the wrong numbers.
+			assert_eq!(count("tests"), cases.len());
+			assert_eq!(count("failures"), cases.iter().filter(|x| x.1.is_some()).count());
+			Suite { name: suite.attribute("name").unwrap().to_string(), tests: count("tests"), failures: count("failures"), cases }
+		}).collect()
+	}
+	
+	fn report(rules : Option<&str>, min_severity : Option<Severity>, policy : Option<&str>) -> String {
+		let (left, right) = scans();
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		let rules = rules.map(|x| SeverityRules::parse(x).unwrap());
+		let policy = policy.map(|x| Policy::parse(x).unwrap().check(&deltas, |_, _| false));
+		to_junit("a.xml", "b&c.xml", &right, &deltas, rules.as_ref(), min_severity, policy.as_ref())
+	}
+	
+	#[test]
+	fn escapes_hostnames_and_services() {
+		let suites = parse(&report(None, None, Some("no-open port=23\nno-new-hosts scope=192.168.0.0/16")));
+		assert_eq!(suites.len(), 2);
+		
+		assert_eq!(suites[0].name, "hosts: a.xml => b&c.xml");
+		assert_eq!((suites[0].tests, suites[0].failures), (4, 3));
+		let cases = &suites[0].cases;
+		let gateway = cases.iter().find(|x| x.0.starts_with("o'brien")).unwrap();
+		assert_eq!(gateway.0, "o'brien & co <lab>.example (10.0.0.1)");
+		assert!(gateway.1.as_ref().unwrap().contains("[telnet\"&]"), "{:?}", gateway.1);
+		assert!(cases.iter().any(|x| x.0 == "<no hostname> (10.0.0.3)" && x.1.is_none()), "{:?}", cases);
+		
+		assert_eq!(suites[1].name, "policy: a.xml => b&c.xml");
+		assert_eq!((suites[1].tests, suites[1].failures), (2, 1));
+		assert_eq!(suites[1].cases[0], ("no-open port=23".to_string(), Some("o'brien & co <lab>.example (10.0.0.1): tcp 23 (telnet\"&)".to_string())));
+	}
+	
+	#[test]
+	fn leaves_out_hosts_below_the_minimum_severity() {
+		let rules = "critical port=23\ninfo port=8080";
+		let counts = |min : Option<Severity>| parse(&report(Some(rules), min, None)).iter().map(|x| (x.tests, x.failures)).collect::<Vec<(usize, usize)>>();
+		
+		assert_eq!(counts(None), vec![(4, 3)]);
+		// The gone host's port closing gets the default severity, and the unchanged host has none at all.
+		assert_eq!(counts(Some(Severity::Info)), vec![(3, 3)]);
+		assert_eq!(counts(Some(Severity::Medium)), vec![(2, 2)]);
+		assert_eq!(counts(Some(Severity::Critical)), vec![(1, 1)]);
+		
+		let suites = parse(&report(Some(rules), Some(Severity::Critical), None));
+		assert!(suites[0].cases[0].1.as_ref().unwrap().ends_with("| Severity: critical\n|   tcp 23 opened (telnet\"&): critical"), "{:?}", suites[0].cases);
+	}
+	
+	#[test]
+	fn escapes_every_special_character() {
+		assert_eq!(xml_escape(r#"<a href="x">'&'</a>"#), "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;");
+		assert_eq!(xml_escape("plain text"), "plain text");
+	}
+}
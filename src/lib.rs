@@ -1,2 +1,19 @@
 pub mod host;
+pub mod details;
+pub mod scan;
+pub mod scope;
+pub mod severity;
+pub mod policy;
+pub mod ignore;
+pub mod rulefile;
+pub mod history;
+pub mod watch;
+pub mod notify;
+pub mod sarif;
+pub mod junit;
+pub mod export;
+pub mod targets;
+pub mod palette;
 pub mod gui;
+#[cfg(test)]
+mod fixtures;
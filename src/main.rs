@@ -1,80 +1,529 @@
+use std::cmp::Reverse;
 use std::fs;
-use chrono::DateTime;
+use std::process;
+use std::path::Path;
+use std::str::FromStr;
+use chrono::Local;
 use clap::Parser;
+use clap::Subcommand;
 
 use ndiff_rs::host::HostDelta;
-use nmap_xml_parser::NmapResults;
+use ndiff_rs::host::DiffOptions;
+use ndiff_rs::host::StateClasses;
+use ndiff_rs::host::CoveragePolicy;
+use ndiff_rs::host::DownHostPolicy;
+use ndiff_rs::scan::Scan;
+use ndiff_rs::scan::ScanComparison;
+use ndiff_rs::scope::Scope;
+use ndiff_rs::scope::Cidr;
+use ndiff_rs::scope::parse_list;
+use ndiff_rs::scope::load_list;
+use ndiff_rs::severity::Severity;
+use ndiff_rs::severity::SeverityRules;
+use ndiff_rs::severity::Assessment;
+use ndiff_rs::policy::Policy;
+use ndiff_rs::ignore::IgnoreList;
+use ndiff_rs::history::HistoryStore;
+use ndiff_rs::watch::Watcher;
+use ndiff_rs::notify::Email;
+use ndiff_rs::notify::Notifier;
+use ndiff_rs::notify::Summary;
+use ndiff_rs::notify::Trigger;
+use ndiff_rs::notify::WebhookFormat;
+use ndiff_rs::sarif::to_sarif;
+use ndiff_rs::junit::to_junit;
+use ndiff_rs::export::write_changed_hosts;
+use ndiff_rs::targets::TargetList;
 
 #[derive(Parser, Debug)]
-#[command(version, about = "A diffing tool for NMap scans in XML format.", long_about = None)]
+#[command(version, about = "A diffing tool for NMap scans in XML format.", long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+	#[command(subcommand)]
+	command: Option<Command>,
 	left_scan: Option<String>,
 	right_scan: Option<String>,
 	#[arg(short, long)]
-	gui: bool
+	gui: bool,
+	#[command(flatten)]
+	diff: DiffArgs
 }
 
-#[derive(Debug)]
-pub enum Error {
-	FileRead(std::io::Error),
-	FileParse(nmap_xml_parser::Error)
+/// Options which control how a pair of scans is diffed and reported on.
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+	/// Ignore changes in OS guess accuracy of up to this many percentage points
+	#[arg(long, default_value_t = 5)]
+	os_threshold: u8,
+	/// Ignore changes in a host's estimated last boot time of up to this many seconds
+	#[arg(long, default_value_t = 300)]
+	reboot_tolerance: i64,
+	/// Also report changes in network distance and traceroute path
+	#[arg(long)]
+	routes: bool,
+	/// Treat a comma-separated list of port states as equivalent, e.g. "filtered,open|filtered" (can be repeated)
+	#[arg(long = "state-class", value_name = "STATES")]
+	state_classes: Vec<String>,
+	/// Only report ports becoming open or ceasing to be open, treating every other state as equivalent
+	#[arg(long, conflicts_with = "state_classes")]
+	exposure_only: bool,
+	/// What to do with ports that only one of the scans covered: ignore, flag or suppress
+	#[arg(long, default_value = "flag")]
+	coverage: CoveragePolicy,
+	/// Whether hosts reported as down count as present (so going down is a status change) or absent (so going down is reported as gone)
+	#[arg(long, default_value = "present")]
+	down_hosts: DownHostPolicy,
+	/// Only diff hosts within these IPv4 or IPv6 networks, e.g. "10.0.0.0/24,2001:db8::/32" (can be repeated)
+	#[arg(long, value_name = "CIDRS")]
+	include: Vec<String>,
+	/// Don't diff hosts within these networks (can be repeated)
+	#[arg(long, value_name = "CIDRS")]
+	exclude: Vec<String>,
+	/// Read networks to include from a file, one or more per line
+	#[arg(long, value_name = "FILE")]
+	include_from: Vec<String>,
+	/// Read networks to exclude from a file, one or more per line
+	#[arg(long, value_name = "FILE")]
+	exclude_from: Vec<String>,
+	/// Assign severities to changes using a rules file, and sort the output by them
+	#[arg(long, value_name = "FILE")]
+	rules: Option<String>,
+	/// Only report hosts with changes of at least this severity: info, low, medium, high or critical
+	#[arg(long, value_name = "SEVERITY")]
+	min_severity: Option<Severity>,
+	/// Check the diff against a policy file, exiting with a non-zero status if any of its rules are violated
+	#[arg(long, value_name = "FILE")]
+	policy: Option<String>,
+	/// Suppress known, accepted changes listed in this file, such as .ndiffignore
+	#[arg(long, value_name = "FILE")]
+	ignore_file: Option<String>,
+	/// Record both scans and the diff between them in a history store, which is created if it doesn't exist
+	#[arg(long, value_name = "FILE")]
+	store: Option<String>,
+	/// Post a JSON summary of the diff to this webhook URL
+	#[arg(long, value_name = "URL")]
+	webhook: Option<String>,
+	/// The webhook payload format: generic, slack, mattermost or teams
+	#[arg(long, default_value = "generic")]
+	webhook_format: WebhookFormat,
+	/// Email a summary of the diff through this SMTP server, without TLS or authentication
+	#[arg(long, value_name = "HOST:PORT", requires = "mail_to")]
+	smtp_server: Option<String>,
+	#[arg(long, default_value = "ndiff-rs@localhost")]
+	mail_from: String,
+	/// An address to email the summary to (can be repeated)
+	#[arg(long)]
+	mail_to: Vec<String>,
+	/// When to send notifications: exposure (a port became open), changes or always
+	#[arg(long, default_value = "exposure")]
+	notify_on: Trigger,
+	/// The report format: text, sarif for code scanning dashboards, or junit for CI test reports
+	#[arg(long, default_value = "text")]
+	format: OutputFormat,
+	/// Write the new and changed hosts from the right-hand scan to this file as Nmap XML, keeping their original elements
+	#[arg(long, value_name = "FILE")]
+	export_xml: Option<String>
 }
 
-fn load_scan(path : &str) -> Result<NmapResults,Error> {
-	let content = match fs::read_to_string(path) {
-		Ok(x) => x,
-		Err(e) => return Err(Error::FileRead(e))
+/// How the report is written out.
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum OutputFormat {
+	Text,
+	Sarif,
+	Junit
+}
+
+impl OutputFormat {
+	/// The extension for report files written in this format.
+	fn extension(&self) -> &'static str {
+		match self {
+			OutputFormat::Text => "diff.txt",
+			OutputFormat::Sarif => "sarif",
+			OutputFormat::Junit => "junit.xml"
+		}
+	}
+}
+
+impl FromStr for OutputFormat {
+	type Err = String;
+	
+	fn from_str(s : &str) -> Result<Self,Self::Err> {
+		match s {
+			"text" => Ok(OutputFormat::Text),
+			"sarif" => Ok(OutputFormat::Sarif),
+			"junit" => Ok(OutputFormat::Junit),
+			_ => Err(format!("unknown format '{}' (expected text, sarif or junit)", s))
+		}
+	}
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+	/// Show what happened to a host, given by address or hostname, in every scan in a history store
+	History {
+		host: String,
+		#[arg(long, value_name = "FILE")]
+		store: String
+	},
+	/// Find when ports were first and last seen in a given state, across every scan in a history store
+	Query {
+		#[arg(long, value_name = "FILE")]
+		store: String,
+		/// Only include this host, given by address or hostname
+		#[arg(long)]
+		host: Option<String>,
+		/// Only include this port number
+		#[arg(long)]
+		port: Option<u16>,
+		#[arg(long, default_value = "open")]
+		state: String
+	},
+	/// Watch a directory for new scans, and diff each one against the previous scan of the same targets
+	Watch {
+		directory: String,
+		/// Also write each report to a file in this directory, named after the new scan
+		#[arg(long, value_name = "DIR")]
+		report_dir: Option<String>,
+		/// Stop watching and exit with a non-zero status as soon as a scan violates the policy
+		#[arg(long)]
+		exit_on_violation: bool,
+		#[command(flatten)]
+		diff: Box<DiffArgs>
+	},
+	/// List the hosts affected by a diff, one address per line for Nmap's -iL option, so that they can be re-scanned
+	Targets {
+		left_scan: String,
+		right_scan: String,
+		/// Print the affected ports as a -p port spec instead of the addresses. UDP and SCTP ports also need -sU and -sY
+		#[arg(long, conflicts_with = "output_dir")]
+		port_spec: bool,
+		/// Write address lists to this directory for each group of hosts, one per address family, and print an Nmap command line to
+		/// re-scan each one
+		#[arg(long, value_name = "DIR")]
+		output_dir: Option<String>,
+		/// Group the hosts by whether they are new, changed or gone, rather than writing a single list
+		#[arg(long, requires = "output_dir")]
+		split: bool,
+		#[command(flatten)]
+		diff: Box<DiffArgs>
+	}
+}
+
+/// Everything loaded from the command line that's needed to diff and report on a pair of scans.
+struct Config {
+	options: DiffOptions,
+	rules: Option<SeverityRules>,
+	min_severity: Option<Severity>,
+	policy: Option<Policy>,
+	ignore: Option<IgnoreList>,
+	store: Option<String>,
+	notifier: Option<Notifier>,
+	format: OutputFormat,
+	export_xml: Option<String>
+}
+
+impl Config {
+	fn load(args : &DiffArgs) -> Result<Config,String> {
+		let options = diff_options(args)?;
+		
+		// Severities are only worked out if they were asked for, so that the output is otherwise unchanged.
+		let rules = match &args.rules {
+			Some(path) => Some(SeverityRules::load(path).map_err(|e| format!("Invalid rules: {}", e))?),
+			None => args.min_severity.map(|_| SeverityRules::default())
+		};
+		
+		let policy = match &args.policy {
+			Some(path) => Some(Policy::load(path).map_err(|e| format!("Invalid policy: {}", e))?),
+			None => None
+		};
+		
+		let ignore = match &args.ignore_file {
+			Some(path) => Some(IgnoreList::load(path).map_err(|e| format!("Invalid ignore file: {}", e))?),
+			None => None
+		};
+		
+		let webhook = args.webhook.clone().map(|x| (x, args.webhook_format));
+		let email = args.smtp_server.clone().map(|server| Email { server, from: args.mail_from.clone(), to: args.mail_to.clone() });
+		let notifier = match webhook.is_some() || email.is_some() {
+			true => Some(Notifier { webhook, email, trigger: args.notify_on }),
+			false => None
+		};
+		
+		Ok(Config { options, rules, min_severity: args.min_severity, policy, ignore, store: args.store.clone(), notifier, format: args.format, export_xml: args.export_xml.clone() })
+	}
+}
+
+fn run_command(command : &Command) -> Result<(),String> {
+	match command {
+		Command::History { host, store } => {
+			let lines = HistoryStore::open_read_only(store)?.host_history(host)?;
+			match lines.is_empty() {
+				true => println!("No scans in the store."),
+				false => for line in lines { println!("{}", line); }
+			};
+		},
+		Command::Query { store, host, port, state } => {
+			let sightings = HistoryStore::open_read_only(store)?.sightings(host.as_deref(), *port, state)?;
+			match sightings.is_empty() {
+				true => println!("No matching ports in the store."),
+				false => for sighting in sightings { println!("{}", sighting); }
+			};
+		},
+		Command::Watch { directory, report_dir, exit_on_violation, diff } => {
+			let config = Config::load(diff)?;
+			let mut watcher = Watcher::new(directory)?;
+			println!("Watching {} for new scans...", directory);
+			
+			loop {
+				for result in watcher.wait()? {
+					let new_scan = match result {
+						Ok(x) => x,
+						Err(e) => {
+							eprintln!("{}", e);
+							// A scan that can't be read can't be checked either, so it counts as a violation.
+							if *exit_on_violation { process::exit(ERROR_STATUS); }
+							continue;
+						}
+					};
+					let (previous_path, previous) = match new_scan.previous {
+						Some(x) => x,
+						None if new_scan.late => { println!("{}: a later scan of the same targets has already been diffed\n", new_scan.path); continue; },
+						None => { println!("{}: no previous scan of the same targets\n", new_scan.path); continue; }
+					};
+					
+					let (text, passed) = match report(&config, &previous_path, &previous, &new_scan.path, &new_scan.scan) {
+						Ok(x) => x,
+						Err(e) => {
+							eprintln!("{}: {}", new_scan.path, e);
+							// The run failed, so like a scan that can't be read, it counts as a violation.
+							if *exit_on_violation { process::exit(ERROR_STATUS); }
+							continue;
+						}
+					};
+					println!("{} => {}", previous_path, new_scan.path);
+					print!("{}", text);
+					if let Some(report_dir) = report_dir {
+						let name = Path::new(&new_scan.path).file_stem().unwrap_or_default().to_string_lossy().to_string();
+						let path = Path::new(report_dir).join(format!("{}.{}", name, config.format.extension()));
+						if let Err(e) = fs::write(&path, &text) { eprintln!("Failed to write '{}': {}", path.display(), e); }
+					}
+					if !passed {
+						eprintln!("ALERT: {} violates the policy", new_scan.path);
+						if *exit_on_violation { process::exit(1); }
+					}
+				}
+			}
+		},
+		Command::Targets { left_scan, right_scan, port_spec, output_dir, split, diff } => {
+			let config = Config::load(diff)?;
+			let left = Scan::load(left_scan).map_err(|e| format!("Failed to parse '{}': {:?}", left_scan, e))?;
+			let right = Scan::load(right_scan).map_err(|e| format!("Failed to parse '{}': {:?}", right_scan, e))?;
+			
+			let mut deltas = HostDelta::from_scans(&left, &right, &config.options);
+			if let Some(ignore) = &config.ignore {
+				deltas = ignore.apply(deltas, Local::now().date_naive()).0;
+			}
+			if let (Some(rules), Some(min)) = (&config.rules, config.min_severity) {
+				deltas.retain(|x| rules.assess(x).is_some_and(|x| x.severity >= min));
+			}
+			
+			let groups : Vec<(String, TargetList)> = match split {
+				true => TargetList::by_kind(&deltas).into_iter().map(|(kind, targets)| (kind.to_string().to_lowercase(), targets)).collect(),
+				false => vec![("targets".to_string(), TargetList::from_deltas(deltas.iter()))]
+			};
+			
+			match output_dir {
+				Some(dir) => {
+					fs::create_dir_all(dir).map_err(|e| format!("Failed to create '{}': {}", dir, e))?;
+					for (name, targets) in groups.iter().filter(|(_, targets)| !targets.is_empty()) {
+						// IPv4 and IPv6 addresses have to be scanned separately, so each family gets its own list and command.
+						for (suffix, addresses, ipv6) in [("", &targets.ipv4, false), ("-ipv6", &targets.ipv6, true)] {
+							if addresses.is_empty() { continue; }
+							let path = Path::new(dir).join(format!("{}{}.txt", name, suffix));
+							let content : String = addresses.iter().map(|x| format!("{}\n", x)).collect();
+							fs::write(&path, content).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+							println!("{}", targets.command(&path.display().to_string(), ipv6));
+						}
+					}
+				},
+				None => for (_, targets) in groups {
+					match port_spec {
+						true => if let Some(spec) = targets.port_spec() { println!("{}", spec); },
+						false => {
+							if !targets.ipv4.is_empty() && !targets.ipv6.is_empty() {
+								eprintln!("Nmap has to scan IPv4 and IPv6 addresses separately (with -6 for IPv6), so use --output-dir to get a list of each");
+							}
+							for address in targets.ipv4.iter().chain(&targets.ipv6) { println!("{}", address); }
+						}
+					};
+				}
+			};
+		}
+	}
+	Ok(())
+}
+
+/// Diffs two scans and builds the report, returning it along with whether the policy (if there is one) passed. Fails if the
+/// changed hosts couldn't be exported, since whatever reads the export would otherwise get a missing or stale file.
+fn report(config : &Config, left_path : &str, left : &Scan, right_path : &str, right : &Scan) -> Result<(String, bool),String> {
+	let mut deltas = HostDelta::from_scans(left, right, &config.options);
+	
+	// The store gets every change, even the ones that are suppressed from the report below.
+	if let Some(path) = &config.store {
+		let result = HistoryStore::open(path).and_then(|mut store| {
+			store.add_scan(left, left_path)?;
+			store.add_scan(right, right_path)?;
+			store.add_deltas(left, right, &deltas)?;
+			store.save()
+		});
+		if let Err(e) = result { eprintln!("Failed to update the history store: {}", e); }
+	}
+	
+	// The policy sees every change too, so that an ignore entry can only exempt the ports it accepts, rather than hiding a
+	// whole host from the policy.
+	let today = Local::now().date_naive();
+	let policy_report = config.policy.as_ref().map(|x| x.check(&deltas, |delta, port| config.ignore.as_ref().is_some_and(|ignore| ignore.accepts(delta, port, today))));
+	let passed = policy_report.as_ref().is_none_or(|x| x.passed());
+	
+	let suppressed = match &config.ignore {
+		Some(ignore) => {
+			let (remaining, suppressed) = ignore.apply(deltas, today);
+			deltas = remaining;
+			Some(suppressed)
+		},
+		None => None
 	};
 	
-	let results = match NmapResults::parse(&content) {
-		Ok(x) => x,
-		Err(e) => return Err(Error::FileParse(e))
+	if let Some(notifier) = &config.notifier {
+		for e in notifier.notify(&Summary::from_deltas(left_path, right_path, &deltas)) {
+			eprintln!("{}", e);
+		}
+	}
+	
+	if let Some(path) = &config.export_xml {
+		write_changed_hosts(path, right, &deltas).map_err(|e| format!("Failed to export the changed hosts: {}", e))?;
+	}
+	
+	let output = match config.format {
+		OutputFormat::Text => {
+			let mut output = format!("{}\n", ScanComparison::from_scans(left, right));
+			match &config.rules {
+				Some(rules) => {
+					let mut assessed : Vec<(&HostDelta, Option<Assessment>)> = deltas.iter().map(|x| (x, rules.assess(x))).collect();
+					assessed.retain(|(_, assessment)| config.min_severity.is_none_or(|min| assessment.as_ref().is_some_and(|x| x.severity >= min)));
+					assessed.sort_by_key(|(_, assessment)| Reverse(assessment.as_ref().map(|x| x.severity)));
+					for (delta, assessment) in assessed {
+						match assessment {
+							Some(x) => output.push_str(&format!("{}\n{}\n", delta.to_string().trim_end(), x)),
+							None => output.push_str(&delta.to_string())
+						}
+					}
+				},
+				None => for delta in &deltas {
+					output.push_str(&delta.to_string());
+				}
+			}
+			
+			if let Some(suppressed) = suppressed {
+				output.push_str(&format!("{}\n", suppressed));
+			}
+			if let Some(report) = &policy_report {
+				output.push_str(&report.to_string());
+			}
+			output
+		},
+		OutputFormat::Sarif => {
+			let rules = config.rules.clone().unwrap_or_default();
+			to_sarif(right_path, &deltas, &rules, config.min_severity, policy_report.as_ref())
+		},
+		OutputFormat::Junit => to_junit(left_path, right_path, right, &deltas, config.rules.as_ref(), config.min_severity, policy_report.as_ref())
 	};
 	
-	Ok(results)
+	Ok((output, passed))
 }
 
-fn get_time(scan : &NmapResults) -> String {
-	match DateTime::from_timestamp(scan.scan_start_time, 0) {
-		Some(x) => format!("{}", x),
-		None => "<unknown start time>".to_string()
+/// Gathers networks from the command line and from files into a single list.
+fn networks(lists : &[String], files : &[String]) -> Result<Vec<Cidr>,String> {
+	let mut output = Vec::new();
+	for list in lists {
+		output.extend(parse_list(list)?);
+	}
+	for file in files {
+		output.extend(load_list(file)?);
 	}
+	Ok(output)
+}
+
+fn diff_options(args : &DiffArgs) -> Result<DiffOptions,String> {
+	let state_classes = match args.exposure_only {
+		true => StateClasses::exposure(),
+		false => match args.state_classes.iter().map(|x| StateClasses::parse_class(x)).collect() {
+			Ok(x) => StateClasses(x),
+			Err(e) => return Err(format!("Invalid state class: {}", e))
+		}
+	};
+	
+	let scope = match (networks(&args.include, &args.include_from), networks(&args.exclude, &args.exclude_from)) {
+		(Ok(include), Ok(exclude)) => Scope { include, exclude },
+		(Err(e), _) | (_, Err(e)) => return Err(format!("Invalid network: {}", e))
+	};
+	
+	Ok(DiffOptions {
+		os_accuracy_threshold: args.os_threshold,
+		reboot_tolerance: args.reboot_tolerance,
+		compare_routes: args.routes,
+		state_classes,
+		coverage: args.coverage,
+		down_hosts: args.down_hosts,
+		scope
+	})
 }
 
+/// The exit status for configuration errors and scans that couldn't be loaded, as opposed to 1 for a failed policy, so that
+/// a CI gate fails closed and the two can still be told apart.
+const ERROR_STATUS : i32 = 2;
+
 fn main() {
 	let args = Args::parse();
 	
-	if args.left_scan.is_none() && args.right_scan.is_none() {
-		if args.gui {
+	if let Some(command) = &args.command {
+		if let Err(e) = run_command(command) {
+			eprintln!("{}", e);
+			process::exit(ERROR_STATUS);
+		}
+		return;
+	}
+	
+	let (left_scan, right_scan) = match (args.left_scan, args.right_scan) {
+		(Some(left), Some(right)) => (left, right),
+		(None, None) if args.gui => {
 			let _ = ndiff_rs::gui::run_gui();
 			return;
-		} else {
+		},
+		_ => {
 			eprintln!("SYNTAX: ndiff-rs first.xml second.xml");
-			return;
+			process::exit(ERROR_STATUS);
 		}
-	}
-	
-	let left_scan : String = args.left_scan.unwrap().clone();
-	let right_scan : String = args.right_scan.unwrap().clone();
+	};
 	
-	let left = match load_scan(&left_scan) {
+	let config = match Config::load(&args.diff) {
 		Ok(x) => x,
-		Err(e) => { println!("Failed to parse {}: {:?}", left_scan, e); return; }
+		Err(e) => { eprintln!("{}", e); process::exit(ERROR_STATUS); }
 	};
 	
-	let right = match load_scan(&right_scan) {
+	let left = match Scan::load(&left_scan) {
 		Ok(x) => x,
-		Err(e) => { println!("Failed to parse '{}': {:?}", right_scan, e); return; }
+		Err(e) => { eprintln!("Failed to parse '{}': {:?}", left_scan, e); process::exit(ERROR_STATUS); }
 	};
 	
-	println!("Left Scan: {}", get_time(&left)); 
-	println!("Right Scan: {}", get_time(&right));
-	println!("");
-	
+	let right = match Scan::load(&right_scan) {
+		Ok(x) => x,
+		Err(e) => { eprintln!("Failed to parse '{}': {:?}", right_scan, e); process::exit(ERROR_STATUS); }
+	};
 	
-	let deltas = HostDelta::from_scans(&left, &right);
-	for delta in &deltas {
-		print!("{}", delta.to_string());
-	}
-
+	let (text, passed) = match report(&config, &left_scan, &left, &right_scan, &right) {
+		Ok(x) => x,
+		Err(e) => { eprintln!("{}", e); process::exit(ERROR_STATUS); }
+	};
+	print!("{}", text);
+	if !passed { process::exit(1); }
 }
@@ -0,0 +1,430 @@
+use std::fmt;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::host::DeltaKind;
+use crate::host::HostDelta;
+use crate::host::HostWrapper;
+use crate::severity::PortEvent;
+use crate::severity::port_event;
+
+const TIMEOUT : Duration = Duration::from_secs(10);
+
+/// A short summary of a diff, which is what gets sent in notifications.
+#[derive(Debug,Clone)]
+pub struct Summary {
+	pub left: String,
+	pub right: String,
+	pub new_hosts: usize,
+	pub gone_hosts: usize,
+	pub changed_hosts: usize,
+	/// Ports which became open, as (host title, protocol, port, service).
+	pub opened: Vec<(String,String,u16,String)>,
+	/// Ports which stopped being open, as (host title, protocol, port, service).
+	pub closed: Vec<(String,String,u16,String)>
+}
+
+/// The shape of the JSON payload posted to a webhook.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum WebhookFormat {
+	/// Every field of the summary, for webhooks that process it themselves.
+	Generic,
+	/// A `text` message, which Slack and Mattermost incoming webhooks both accept.
+	Slack,
+	/// A MessageCard for a Microsoft Teams incoming webhook.
+	Teams
+}
+
+/// When to send notifications.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Trigger {
+	/// Only when a port became open.
+	Exposure,
+	/// When any host changed, appeared or disappeared.
+	Changes,
+	/// After every diff.
+	Always
+}
+
+/// Where to send email notifications. Only plain SMTP is supported, so this will usually be a local relay.
+#[derive(Debug,Clone)]
+pub struct Email {
+	pub server: String,
+	pub from: String,
+	pub to: Vec<String>
+}
+
+/// Sends a summary of each diff to a webhook, by email, or both.
+#[derive(Debug,Clone)]
+pub struct Notifier {
+	pub webhook: Option<(String,WebhookFormat)>,
+	pub email: Option<Email>,
+	pub trigger: Trigger
+}
+
+impl Summary {
+	pub fn from_deltas(left : &str, right : &str, deltas : &[HostDelta]) -> Summary {
+		let count = |kind : DeltaKind| deltas.iter().filter(|x| x.kind() == kind).count();
+		let mut summary = Summary {
+			left: left.to_string(),
+			right: right.to_string(),
+			new_hosts: count(DeltaKind::New),
+			gone_hosts: count(DeltaKind::Gone),
+			changed_hosts: count(DeltaKind::Changed),
+			opened: Vec::new(),
+			closed: Vec::new()
+		};
+		
+		for delta in deltas {
+			let title = HostWrapper(delta.host().clone()).get_title();
+			for change in delta.port_changes() {
+				let (port, event) = match port_event(&change) {
+					Some(x) => x,
+					None => continue
+				};
+				let entry = (title.clone(), port.protocol.to_string(), port.port_number, port.service_info.as_ref().map(|x| x.name.clone()).unwrap_or_default());
+				match event {
+					PortEvent::Opened => summary.opened.push(entry),
+					PortEvent::Closed => summary.closed.push(entry),
+					PortEvent::Changed => ()
+				};
+			}
+		}
+		
+		summary
+	}
+	
+	/// Returns the summary as a JSON payload in the given format.
+	pub fn to_json(&self, format : WebhookFormat) -> String {
+		match format {
+			WebhookFormat::Generic => {
+				let ports = |ports : &Vec<(String,String,u16,String)>| ports.iter().map(|(host, protocol, port, service)| {
+					format!("{{\"host\":{},\"protocol\":{},\"port\":{},\"service\":{}}}", json_string(host), json_string(protocol), port, json_string(service))
+				}).collect::<Vec<String>>().join(",");
+				format!(
+					"{{\"left\":{},\"right\":{},\"new_hosts\":{},\"gone_hosts\":{},\"changed_hosts\":{},\"opened\":[{}],\"closed\":[{}],\"text\":{}}}",
+					json_string(&self.left), json_string(&self.right), self.new_hosts, self.gone_hosts, self.changed_hosts, ports(&self.opened), ports(&self.closed), json_string(&self.to_string())
+				)
+			},
+			WebhookFormat::Slack => format!("{{\"text\":{}}}", json_string(&self.to_string())),
+			WebhookFormat::Teams => format!(
+				"{{\"@type\":\"MessageCard\",\"@context\":\"https://schema.org/extensions\",\"summary\":{},\"title\":{},\"text\":{}}}",
+				json_string(&self.headline()), json_string(&self.headline()), json_string(&self.to_string().replace('\n', "\n\n"))
+			)
+		}
+	}
+	
+	fn headline(&self) -> String {
+		format!("ndiff-rs: {} => {}", self.left, self.right)
+	}
+}
+
+impl Notifier {
+	pub fn should_notify(&self, summary : &Summary) -> bool {
+		match self.trigger {
+			Trigger::Exposure => !summary.opened.is_empty(),
+			Trigger::Changes => summary.new_hosts + summary.gone_hosts + summary.changed_hosts > 0,
+			Trigger::Always => true
+		}
+	}
+	
+	/// Sends the summary to every configured destination, if the trigger was met. Returns the errors from any that failed.
+	pub fn notify(&self, summary : &Summary) -> Vec<String> {
+		let mut errors : Vec<String> = Vec::new();
+		if !self.should_notify(summary) { return errors; }
+		
+		if let Some((url, format)) = &self.webhook && let Err(e) = post_webhook(url, &summary.to_json(*format)) {
+			errors.push(format!("Failed to post to the webhook: {}", e));
+		}
+		if let Some(email) = &self.email && let Err(e) = send_email(email, &summary.headline(), &summary.to_string()) {
+			errors.push(format!("Failed to send email: {}", e));
+		}
+		
+		errors
+	}
+}
+
+/// Posts a JSON payload to a webhook URL, e.g. "https://hooks.slack.com/services/...".
+pub fn post_webhook(url : &str, payload : &str) -> Result<(),String> {
+	let result = ureq::post(url)
+		.timeout(TIMEOUT)
+		.set("Content-Type", "application/json")
+		.set("User-Agent", "ndiff-rs")
+		.send_string(payload);
+	
+	match result {
+		Ok(_) => Ok(()),
+		Err(ureq::Error::Status(code, response)) => Err(format!("the server responded with '{} {}'", code, response.status_text())),
+		Err(e) => Err(e.to_string())
+	}
+}
+
+/// Sends a plain text email over SMTP, without TLS or authentication.
+pub fn send_email(email : &Email, subject : &str, body : &str) -> Result<(),String> {
+	let stream = connect(&email.server)?;
+	let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+	let mut writer = stream;
+	
+	// Sends a command (or nothing, for the greeting) and checks that the reply has the expected code.
+	let mut exchange = |command : Option<String>, expected : &str| -> Result<(),String> {
+		if let Some(command) = &command {
+			writer.write_all(format!("{}\r\n", command).as_bytes()).map_err(|e| e.to_string())?;
+		}
+		// Multi-line replies have a '-' after the code on every line but the last.
+		loop {
+			let mut line = String::new();
+			match reader.read_line(&mut line) {
+				Ok(0) => return Err("the server closed the connection".to_string()),
+				Ok(_) => (),
+				Err(e) => return Err(e.to_string())
+			};
+			if !line.starts_with(expected) {
+				return Err(format!("unexpected reply '{}' to {}", line.trim_end(), command.unwrap_or("the connection".to_string())));
+			}
+			if line.as_bytes().get(3) != Some(&b'-') { return Ok(()); }
+		}
+	};
+	
+	exchange(None, "220")?;
+	exchange(Some("HELO ndiff-rs".to_string()), "250")?;
+	exchange(Some(format!("MAIL FROM:<{}>", email.from)), "250")?;
+	for to in &email.to {
+		exchange(Some(format!("RCPT TO:<{}>", to)), "25")?;
+	}
+	exchange(Some("DATA".to_string()), "354")?;
+	
+	// Lines starting with a '.' have to be doubled up so that they aren't taken as the end of the message.
+	let body : Vec<String> = body.lines().map(|x| match x.starts_with('.') { true => format!(".{}", x), false => x.to_string() }).collect();
+	let message = format!(
+		"From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n.",
+		email.from, email.to.join(", "), subject, Utc::now().to_rfc2822(), body.join("\r\n")
+	);
+	exchange(Some(message), "250")?;
+	exchange(Some("QUIT".to_string()), "221")
+}
+
+fn connect(address : &str) -> Result<TcpStream,String> {
+	let socket = match address.to_socket_addrs().map(|mut x| x.next()) {
+		Ok(Some(x)) => x,
+		_ => return Err(format!("couldn't resolve '{}'", address))
+	};
+	let stream = TcpStream::connect_timeout(&socket, TIMEOUT).map_err(|e| format!("couldn't connect to '{}': {}", address, e))?;
+	let _ = stream.set_read_timeout(Some(TIMEOUT));
+	let _ = stream.set_write_timeout(Some(TIMEOUT));
+	Ok(stream)
+}
+
+pub(crate) fn json_string(value : &str) -> String {
+	let mut output = String::from("\"");
+	for c in value.chars() {
+		match c {
+			'"' => output.push_str("\\\""),
+			'\\' => output.push_str("\\\\"),
+			'\n' => output.push_str("\\n"),
+			'\r' => output.push_str("\\r"),
+			'\t' => output.push_str("\\t"),
+			c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+			c => output.push(c)
+		}
+	}
+	output.push('"');
+	output
+}
+
+impl FromStr for WebhookFormat {
+	type Err = String;
+	
+	fn from_str(s : &str) -> Result<Self,Self::Err> {
+		match s {
+			"generic" => Ok(WebhookFormat::Generic),
+			"slack" | "mattermost" => Ok(WebhookFormat::Slack),
+			"teams" => Ok(WebhookFormat::Teams),
+			_ => Err(format!("unknown webhook format '{}' (expected generic, slack, mattermost or teams)", s))
+		}
+	}
+}
+
+impl FromStr for Trigger {
+	type Err = String;
+	
+	fn from_str(s : &str) -> Result<Self,Self::Err> {
+		match s {
+			"exposure" => Ok(Trigger::Exposure),
+			"changes" => Ok(Trigger::Changes),
+			"always" => Ok(Trigger::Always),
+			_ => Err(format!("unknown trigger '{}' (expected exposure, changes or always)", s))
+		}
+	}
+}
+
+impl fmt::Display for Summary {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "{}", self.headline())?;
+		writeln!(f, "{} new hosts, {} gone hosts, {} changed hosts", self.new_hosts, self.gone_hosts, self.changed_hosts)?;
+		let list = |ports : &Vec<(String,String,u16,String)>| ports.iter().map(|(host, protocol, port, service)| match service.is_empty() {
+			true => format!("{} {} {}", host, protocol, port),
+			false => format!("{} {} {} ({})", host, protocol, port, service)
+		}).collect::<Vec<String>>();
+		for port in list(&self.opened) {
+			writeln!(f, "Opened: {}", port)?;
+		}
+		for port in list(&self.closed) {
+			writeln!(f, "Closed: {}", port)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::BufRead;
+	use std::io::BufReader;
+	use std::io::Read;
+	use std::io::Write;
+	use std::net::TcpListener;
+	use std::thread;
+	
+	use super::*;
+	
+	fn summary() -> Summary {
+		Summary {
+			left: "a.xml".to_string(),
+			right: "b.xml".to_string(),
+			new_hosts: 1,
+			gone_hosts: 0,
+			changed_hosts: 1,
+			opened: vec![("gw.example (10.0.0.1)".to_string(), "tcp".to_string(), 8080, "http-proxy".to_string())],
+			closed: vec![("10.0.0.2".to_string(), "tcp".to_string(), 23, "".to_string())]
+		}
+	}
+	
+	/// Accepts one HTTP request, answers it with the given status line, and returns the request's head and body.
+	fn http_server(status : &'static str) -> (String, thread::JoinHandle<(String, String)>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap().to_string();
+		let handle = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let mut head = String::new();
+			loop {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				if line == "\r\n" { break; }
+				head.push_str(&line);
+			}
+			let length = head.lines()
+				.find_map(|x| x.to_lowercase().strip_prefix("content-length:").map(|x| x.trim().parse::<usize>().unwrap()))
+				.unwrap();
+			let mut body = vec![0; length];
+			reader.read_exact(&mut body).unwrap();
+			
+			let mut writer = stream;
+			writer.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).as_bytes()).unwrap();
+			(head, String::from_utf8(body).unwrap())
+		});
+		(address, handle)
+	}
+	
+	#[test]
+	fn webhook_posts_json() {
+		let (address, server) = http_server("200 OK");
+		let payload = summary().to_json(WebhookFormat::Generic);
+		post_webhook(&format!("http://{}/hooks/nmap", address), &payload).unwrap();
+		
+		let (head, body) = server.join().unwrap();
+		let head = head.to_lowercase();
+		assert!(head.starts_with("post /hooks/nmap http/1.1\r\n"), "{}", head);
+		assert!(head.contains(&format!("host: {}\r\n", address)), "{}", head);
+		assert!(head.contains("content-type: application/json\r\n"), "{}", head);
+		assert!(head.contains(&format!("content-length: {}\r\n", payload.len())), "{}", head);
+		assert_eq!(body, payload);
+		assert!(body.starts_with("{\"left\":\"a.xml\",\"right\":\"b.xml\",\"new_hosts\":1,\"gone_hosts\":0,\"changed_hosts\":1,"), "{}", body);
+		assert!(body.contains("\"opened\":[{\"host\":\"gw.example (10.0.0.1)\",\"protocol\":\"tcp\",\"port\":8080,\"service\":\"http-proxy\"}]"), "{}", body);
+		assert!(body.contains("\"text\":\"ndiff-rs: a.xml => b.xml\\n"), "{}", body);
+	}
+	
+	#[test]
+	fn webhook_reports_error_status() {
+		let (address, server) = http_server("500 Internal Server Error");
+		let result = post_webhook(&format!("http://{}/", address), "{}");
+		server.join().unwrap();
+		assert_eq!(result, Err("the server responded with '500 Internal Server Error'".to_string()));
+	}
+	
+	#[test]
+	fn email_follows_smtp_dialogue() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let server = listener.local_addr().unwrap().to_string();
+		// Plays the server's side of the dialogue, returning every line the client sent.
+		let handle = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let mut writer = stream;
+			let mut lines : Vec<String> = Vec::new();
+			let mut in_data = false;
+			writer.write_all(b"220 localhost ESMTP\r\n").unwrap();
+			loop {
+				let mut line = String::new();
+				if reader.read_line(&mut line).unwrap() == 0 { break; }
+				assert!(line.ends_with("\r\n"), "{:?}", line);
+				let line = line.trim_end_matches("\r\n").to_string();
+				let reply = match (in_data, line.as_str()) {
+					(true, ".") => { in_data = false; Some("250 queued") },
+					(true, _) => None,
+					(false, "DATA") => { in_data = true; Some("354 go ahead") },
+					(false, "QUIT") => Some("221 bye"),
+					// A multi-line reply, to check that the client waits for the last line.
+					(false, x) if x.starts_with("HELO") => Some("250-localhost\r\n250 HELP"),
+					(false, _) => Some("250 OK")
+				};
+				lines.push(line);
+				if let Some(reply) = reply { writer.write_all(format!("{}\r\n", reply).as_bytes()).unwrap(); }
+				if lines.last().unwrap() == "QUIT" { break; }
+			}
+			lines
+		});
+		
+		let email = Email { server, from: "ndiff@example.com".to_string(), to: vec!["a@example.com".to_string(), "b@example.com".to_string()] };
+		send_email(&email, "Subject line", "first line\n.hidden\n..two dots\nlast line").unwrap();
+		let lines = handle.join().unwrap();
+		
+		assert_eq!(&lines[..5], ["HELO ndiff-rs", "MAIL FROM:<ndiff@example.com>", "RCPT TO:<a@example.com>", "RCPT TO:<b@example.com>", "DATA"]);
+		assert_eq!(&lines[lines.len() - 2..], [".", "QUIT"]);
+		
+		let message = &lines[5..lines.len() - 2];
+		assert!(message.contains(&"From: ndiff@example.com".to_string()), "{:?}", message);
+		assert!(message.contains(&"To: a@example.com, b@example.com".to_string()), "{:?}", message);
+		assert!(message.contains(&"Subject: Subject line".to_string()), "{:?}", message);
+		let body = &message[message.iter().position(|x| x.is_empty()).unwrap() + 1..];
+		assert_eq!(body, ["first line", "..hidden", "...two dots", "last line"]);
+	}
+	
+	#[test]
+	fn email_reports_rejected_recipient() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let server = listener.local_addr().unwrap().to_string();
+		let handle = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let mut writer = stream;
+			writer.write_all(b"220 localhost ESMTP\r\n").unwrap();
+			for reply in ["250 localhost", "250 OK", "550 no such user"] {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				writer.write_all(format!("{}\r\n", reply).as_bytes()).unwrap();
+			}
+		});
+		
+		let email = Email { server, from: "ndiff@example.com".to_string(), to: vec!["nobody@example.com".to_string()] };
+		let result = send_email(&email, "Subject", "body");
+		handle.join().unwrap();
+		assert_eq!(result, Err("unexpected reply '550 no such user' to RCPT TO:<nobody@example.com>".to_string()));
+	}
+}
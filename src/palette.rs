@@ -0,0 +1,109 @@
+use std::fmt;
+
+use eframe::egui::Color32;
+
+/// The colour schemes that can be selected in the GUI.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Scheme {
+	Default,
+	ColorblindSafe,
+	HighContrast
+}
+
+/// What a piece of text in the diff represents, independent of how it's coloured.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Meaning {
+	Added,
+	Removed,
+	Changed,
+	Unchanged
+}
+
+/// The colours used to render each Meaning, for a given scheme and theme.
+#[derive(Debug,Clone,Copy)]
+pub struct Palette {
+	pub added: Color32,
+	pub removed: Color32,
+	pub changed: Color32,
+	pub unchanged: Color32
+}
+
+impl Scheme {
+	pub const ALL : [Scheme; 3] = [Scheme::Default, Scheme::ColorblindSafe, Scheme::HighContrast];
+}
+
+impl Meaning {
+	/// A text marker for the meaning, so that it never has to be conveyed by colour alone.
+	pub fn marker(&self) -> &'static str {
+		match self {
+			Meaning::Added => "+",
+			Meaning::Removed => "-",
+			Meaning::Changed => "~",
+			Meaning::Unchanged => " "
+		}
+	}
+}
+
+impl Palette {
+	pub fn new(scheme : Scheme, dark_mode : bool) -> Palette {
+		match (scheme, dark_mode) {
+			(Scheme::Default, false) => Palette {
+				added: Color32::from_rgb(0x0, 0x80, 0x0),
+				removed: Color32::from_rgb(0x80, 0x0, 0x0),
+				changed: Color32::from_rgb(0x8a, 0x5a, 0x0),
+				unchanged: Color32::from_rgb(0x50, 0x50, 0x50)
+			},
+			(Scheme::Default, true) => Palette {
+				added: Color32::from_rgb(0x5c, 0xc8, 0x5c),
+				removed: Color32::from_rgb(0xf0, 0x68, 0x68),
+				changed: Color32::from_rgb(0xe0, 0xb0, 0x40),
+				unchanged: Color32::from_rgb(0xb0, 0xb0, 0xb0)
+			},
+			// Blue and orange from the Okabe-Ito palette, which stay distinct under all common forms of colour blindness.
+			(Scheme::ColorblindSafe, false) => Palette {
+				added: Color32::from_rgb(0x0, 0x72, 0xb2),
+				removed: Color32::from_rgb(0xd5, 0x5e, 0x0),
+				changed: Color32::from_rgb(0xa0, 0x4a, 0x80),
+				unchanged: Color32::from_rgb(0x50, 0x50, 0x50)
+			},
+			(Scheme::ColorblindSafe, true) => Palette {
+				added: Color32::from_rgb(0x56, 0xb4, 0xe9),
+				removed: Color32::from_rgb(0xe6, 0x9f, 0x0),
+				changed: Color32::from_rgb(0xcc, 0x79, 0xa7),
+				unchanged: Color32::from_rgb(0xb0, 0xb0, 0xb0)
+			},
+			(Scheme::HighContrast, false) => Palette {
+				added: Color32::from_rgb(0x0, 0x50, 0x0),
+				removed: Color32::from_rgb(0xa0, 0x0, 0x0),
+				changed: Color32::from_rgb(0x50, 0x30, 0x0),
+				unchanged: Color32::BLACK
+			},
+			(Scheme::HighContrast, true) => Palette {
+				added: Color32::from_rgb(0x80, 0xff, 0x80),
+				removed: Color32::from_rgb(0xff, 0x80, 0x80),
+				changed: Color32::from_rgb(0xff, 0xff, 0x60),
+				unchanged: Color32::WHITE
+			}
+		}
+	}
+
+	pub fn color(&self, meaning : Meaning) -> Color32 {
+		match meaning {
+			Meaning::Added => self.added,
+			Meaning::Removed => self.removed,
+			Meaning::Changed => self.changed,
+			Meaning::Unchanged => self.unchanged
+		}
+	}
+}
+
+impl fmt::Display for Scheme {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let display_str = match self {
+			Scheme::Default => "Default",
+			Scheme::ColorblindSafe => "Colorblind-safe",
+			Scheme::HighContrast => "High contrast"
+		};
+		write!(f, "{}", display_str)
+	}
+}
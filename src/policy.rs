@@ -0,0 +1,261 @@
+use std::fmt;
+
+use nmap_xml_parser::host::Host;
+use nmap_xml_parser::port::Port;
+use nmap_xml_parser::port::PortState;
+
+use crate::host::AddressesWrapper;
+use crate::host::DeltaKind;
+use crate::host::HostDelta;
+use crate::host::HostWrapper;
+use crate::rulefile;
+use crate::rulefile::PortFilter;
+use crate::severity::PortEvent;
+use crate::severity::port_event;
+use crate::scope::Scope;
+use crate::scope::parse_list;
+
+/// What a policy rule forbids.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Assertion {
+	/// No port may become open between the two scans.
+	NoNewOpen,
+	/// No port may be open in the right-hand scan, on any of the hosts being diffed.
+	NoOpen,
+	/// No host may appear in the right-hand scan that wasn't in the left-hand one.
+	NoNewHosts
+}
+
+/// A single line of a policy file, e.g. "no-open port=445 exclude=10.0.0.0/8". Rules only apply to hosts within their scope
+/// and ports matching their conditions.
+#[derive(Debug,Clone)]
+pub struct PolicyRule {
+	pub text: String,
+	pub assertion: Assertion,
+	pub scope: Scope,
+	pub filter: PortFilter
+}
+
+/// A set of rules which a pair of scans has to satisfy, e.g. as a gating step in CI.
+#[derive(Debug,Clone,Default)]
+pub struct Policy {
+	pub rules: Vec<PolicyRule>
+}
+
+/// A host, or a port on a host, which broke a policy rule.
+#[derive(Debug,Clone)]
+pub struct Violation {
+	pub host: String,
+	/// The host's IP addresses, which identify it even if its hostname changes.
+	pub addresses: Vec<String>,
+	pub port: Option<Port>
+}
+
+/// The outcome of checking a policy, with the violations of each rule.
+#[derive(Debug,Clone)]
+pub struct PolicyReport {
+	pub results: Vec<(String,Vec<Violation>)>
+}
+
+impl Policy {
+	pub fn load(path : &str) -> Result<Policy,String> {
+		rulefile::load(path, Policy::parse)
+	}
+	
+	/// Parses a policy file, with one rule per line. Anything after a '#' is a comment.
+	pub fn parse(content : &str) -> Result<Policy,String> {
+		let mut rules : Vec<PolicyRule> = Vec::new();
+		rulefile::parse_lines(content, |line, _| PolicyRule::parse(line).map(|x| rules.push(x)))?;
+		Ok(Policy { rules })
+	}
+	
+	/// Checks every rule against the deltas between two scans, which should already have been filtered by scope and down hosts,
+	/// but not by the ignore list. Ports that `accepted` returns true for (e.g. because the ignore list accepts them on that
+	/// host) don't break no-new-open or no-open rules, but nothing exempts a host from no-new-hosts rules.
+	pub fn check(&self, deltas : &[HostDelta], accepted : impl Fn(&HostDelta, &Port) -> bool) -> PolicyReport {
+		let results = self.rules.iter().map(|rule| (rule.text.clone(), rule.violations(deltas, &accepted))).collect();
+		PolicyReport { results }
+	}
+}
+
+impl PolicyRule {
+	fn parse(line : &str) -> Result<PolicyRule,String> {
+		let mut words = line.split_whitespace();
+		let assertion = match words.next() {
+			Some("no-new-open") => Assertion::NoNewOpen,
+			Some("no-open") => Assertion::NoOpen,
+			Some("no-new-hosts") => Assertion::NoNewHosts,
+			Some(x) => return Err(format!("unknown assertion '{}' (expected no-new-open, no-open or no-new-hosts)", x)),
+			None => return Err("empty rule".to_string())
+		};
+		
+		let mut rule = PolicyRule { text: line.to_string(), assertion, scope: Scope::default(), filter: PortFilter::default() };
+		for condition in words {
+			if rule.filter.parse_condition(condition)? { continue; }
+			match condition.split_once('=') {
+				Some(("scope", x)) => rule.scope.include = parse_list(x)?,
+				Some(("exclude", x)) => rule.scope.exclude = parse_list(x)?,
+				_ => return Err(format!("unknown condition '{}' (expected scope=, exclude=, proto=, port= or service=)", condition))
+			}
+		}
+		
+		Ok(rule)
+	}
+	
+	fn violations(&self, deltas : &[HostDelta], accepted : &impl Fn(&HostDelta, &Port) -> bool) -> Vec<Violation> {
+		let describe = |host : &Host, port : &Port| Violation::new(host, Some(port.clone()));
+		
+		match self.assertion {
+			Assertion::NoNewOpen => deltas.iter().filter(|x| self.scope.contains(x.host())).flat_map(|delta| {
+				delta.port_changes().iter().filter_map(|change| match port_event(change) {
+					Some((port, PortEvent::Opened)) if self.filter.matches(port) && !accepted(delta, port) => Some(describe(delta.host(), port)),
+					_ => None
+				}).collect::<Vec<Violation>>()
+			}).collect(),
+			Assertion::NoOpen => deltas.iter().filter(|x| x.kind() != DeltaKind::Gone && self.scope.contains(x.host())).flat_map(|delta| {
+				delta.host().port_info.ports()
+					.filter(|x| x.status.state == PortState::Open && self.filter.matches(x) && !accepted(delta, x))
+					.map(|port| describe(delta.host(), port))
+					.collect::<Vec<Violation>>()
+			}).collect(),
+			Assertion::NoNewHosts => deltas.iter().filter_map(|delta| match delta {
+				HostDelta::New(host) if self.scope.contains(host) => Some(Violation::new(host, None)),
+				_ => None
+			}).collect()
+		}
+	}
+}
+
+impl Violation {
+	fn new(host : &Host, port : Option<Port>) -> Violation {
+		Violation { host: HostWrapper(host.clone()).get_title(), addresses: AddressesWrapper(host.addresses().cloned().collect()).sorted_ips(), port }
+	}
+}
+
+impl PolicyReport {
+	pub fn passed(&self) -> bool {
+		self.results.iter().all(|(_, violations)| violations.is_empty())
+	}
+}
+
+impl fmt::Display for Violation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.port {
+			Some(port) => match &port.service_info {
+				Some(service) => write!(f, "{}: {} {} ({})", self.host, port.protocol, port.port_number, service.name),
+				None => write!(f, "{}: {} {}", self.host, port.protocol, port.port_number)
+			},
+			None => write!(f, "{}", self.host)
+		}
+	}
+}
+
+impl fmt::Display for PolicyReport {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "[=] Policy")?;
+		for (rule, violations) in &self.results {
+			match violations.is_empty() {
+				true => writeln!(f, "| PASS: {}", rule)?,
+				false => writeln!(f, "| FAIL: {}", rule)?
+			};
+			for violation in violations {
+				writeln!(f, "|   {}", violation)?;
+			}
+		}
+		
+		let failed = self.results.iter().filter(|(_, violations)| !violations.is_empty()).count();
+		match failed {
+			0 => writeln!(f, "Policy passed ({} rules)", self.results.len()),
+			_ => writeln!(f, "Policy failed ({} of {} rules)", failed, self.results.len())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::NaiveDate;
+	use crate::fixtures::*;
+	use crate::host::DiffOptions;
+	use crate::ignore::IgnoreList;
+	
+	/// Diffs a scan where 10.0.0.1 opened 445 and 8080, 10.0.0.2 went away with 80 open, 10.0.0.3 appeared with telnet open
+	/// and 192.168.1.5 kept 445 open.
+	fn diff() -> Vec<HostDelta> {
+		let left = scan(&[
+			host(&["10.0.0.1"], Some("gw.example"), &[port(22, "open"), port(445, "closed")].concat()),
+			host(&["10.0.0.2"], Some("web.example"), &open(&[80])),
+			host(&["192.168.1.5"], Some("nas.example"), &open(&[445]))
+		].concat());
+		let right = scan(&[
+			host(&["10.0.0.1"], Some("gw.example"), &open(&[22, 445, 8080])),
+			host(&["10.0.0.3"], Some("new.example"), &open(&[23])),
+			host(&["192.168.1.5"], Some("nas.example"), &open(&[445]))
+		].concat());
+		HostDelta::from_scans(&left, &right, &DiffOptions::default())
+	}
+	
+	/// Checks a policy against the diff, returning the violations of each rule.
+	fn check(policy : &str, accepted : impl Fn(&HostDelta, &Port) -> bool) -> Vec<Vec<String>> {
+		let report = Policy::parse(policy).unwrap().check(&diff(), accepted);
+		report.results.iter().map(|(_, violations)| violations.iter().map(|x| x.to_string()).collect()).collect()
+	}
+	
+	fn nothing_accepted(_ : &HostDelta, _ : &Port) -> bool {
+		false
+	}
+	
+	#[test]
+	fn parses_rules() {
+		let policy = Policy::parse("# Gate\nno-new-open proto=tcp port=1-1024 # low ports\n\nno-open service=telnet scope=10.0.0.0/8 exclude=10.0.0.5\nno-new-hosts\n").unwrap();
+		assert_eq!(policy.rules.iter().map(|x| x.assertion).collect::<Vec<Assertion>>(), vec![Assertion::NoNewOpen, Assertion::NoOpen, Assertion::NoNewHosts]);
+		assert_eq!(policy.rules[0].text, "no-new-open proto=tcp port=1-1024");
+		assert_eq!(policy.rules[0].filter.ports, vec![(1, 1024)]);
+		assert_eq!(policy.rules[1].scope, Scope { include: parse_list("10.0.0.0/8").unwrap(), exclude: parse_list("10.0.0.5").unwrap() });
+		
+		assert_eq!(Policy::parse("no-closed").unwrap_err(), "unknown assertion 'no-closed' (expected no-new-open, no-open or no-new-hosts) on line 1");
+		assert!(Policy::parse("no-open\nno-open change=opened").unwrap_err().starts_with("unknown condition 'change=opened'"));
+		assert!(Policy::parse("no-open scope=10.0.0.0/33").is_err());
+	}
+	
+	#[test]
+	fn finds_ports_that_opened() {
+		assert_eq!(check("no-new-open", nothing_accepted), vec![vec!["new.example (10.0.0.3): tcp 23", "gw.example (10.0.0.1): tcp 445", "gw.example (10.0.0.1): tcp 8080"]]);
+		assert_eq!(check("no-new-open port=8000-8999\nno-new-open scope=192.168.0.0/16", nothing_accepted), vec![vec!["gw.example (10.0.0.1): tcp 8080"], vec![]]);
+		assert_eq!(check("no-new-open", |_, port| port.port_number == 8080), vec![vec!["new.example (10.0.0.3): tcp 23", "gw.example (10.0.0.1): tcp 445"]]);
+	}
+	
+	#[test]
+	fn finds_open_ports_on_hosts_that_are_still_up() {
+		// Unchanged hosts are checked as well, but gone hosts aren't, since their ports aren't open any more.
+		assert_eq!(check("no-open port=445\nno-open port=80", nothing_accepted), vec![vec!["gw.example (10.0.0.1): tcp 445", "nas.example (192.168.1.5): tcp 445"], vec![]]);
+		assert_eq!(check("no-open port=445 exclude=192.168.0.0/16", nothing_accepted), vec![vec!["gw.example (10.0.0.1): tcp 445"]]);
+		assert_eq!(check("no-open port=445", |delta, _| delta.kind() == DeltaKind::Unchanged), vec![vec!["gw.example (10.0.0.1): tcp 445"]]);
+	}
+	
+	#[test]
+	fn finds_new_hosts() {
+		assert_eq!(check("no-new-hosts\nno-new-hosts scope=192.168.0.0/16", nothing_accepted), vec![vec!["new.example (10.0.0.3)"], vec![]]);
+	}
+	
+	#[test]
+	fn only_exempts_ports_the_ignore_list_accepts() {
+		// A host-level entry accepts every port on the new host, but doesn't stop it from being a new host.
+		let ignore = IgnoreList::parse("address=10.0.0.3 kind=new").unwrap();
+		let today = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+		let accepted = |delta : &HostDelta, port : &Port| ignore.accepts(delta, port, today);
+		assert_eq!(check("no-new-open\nno-open port=23\nno-new-hosts", accepted), vec![vec!["gw.example (10.0.0.1): tcp 445", "gw.example (10.0.0.1): tcp 8080"], vec![], vec!["new.example (10.0.0.3)"]]);
+	}
+	
+	#[test]
+	fn passes_when_no_rule_is_broken() {
+		let report = Policy::parse("no-open port=3389\nno-new-hosts scope=192.168.0.0/16").unwrap().check(&diff(), nothing_accepted);
+		assert!(report.passed());
+		assert_eq!(report.to_string(), "[=] Policy\n| PASS: no-open port=3389\n| PASS: no-new-hosts scope=192.168.0.0/16\nPolicy passed (2 rules)\n");
+		
+		let report = Policy::parse("no-open port=3389\nno-new-hosts").unwrap().check(&diff(), nothing_accepted);
+		assert!(!report.passed());
+		assert_eq!(report.to_string(), "[=] Policy\n| PASS: no-open port=3389\n| FAIL: no-new-hosts\n|   new.example (10.0.0.3)\nPolicy failed (1 of 2 rules)\n");
+		assert!(Policy::default().check(&diff(), nothing_accepted).passed());
+	}
+}
@@ -0,0 +1,60 @@
+use std::fs;
+
+use nmap_xml_parser::port::Port;
+
+use crate::details::parse_ranges;
+
+/// The port conditions that severity rules, policy rules and ignore entries have in common, e.g. "proto=tcp port=1-1024
+/// service=http". Every condition that is set has to match.
+#[derive(Debug,Clone,Default)]
+pub struct PortFilter {
+	pub protocol: Option<String>,
+	pub ports: Vec<(u16,u16)>,
+	pub service: Option<String>
+}
+
+impl PortFilter {
+	/// Adds a condition to the filter, returning false if it isn't a port condition so that the caller can handle it.
+	pub fn parse_condition(&mut self, condition : &str) -> Result<bool,String> {
+		match condition.split_once('=') {
+			Some(("proto", x)) => self.protocol = Some(x.to_lowercase()),
+			Some(("port", x)) => self.ports = parse_ranges(x)?,
+			Some(("service", x)) => self.service = Some(x.to_lowercase()),
+			_ => return Ok(false)
+		};
+		Ok(true)
+	}
+	
+	/// Checks whether the filter has no conditions, and so matches every port.
+	pub fn is_empty(&self) -> bool {
+		self.protocol.is_none() && self.ports.is_empty() && self.service.is_none()
+	}
+	
+	pub fn matches(&self, port : &Port) -> bool {
+		let service = port.service_info.as_ref().map(|x| x.name.to_lowercase());
+		
+		self.protocol.as_ref().is_none_or(|x| *x == port.protocol.to_string())
+			&& (self.ports.is_empty() || self.ports.iter().any(|(start, end)| (*start..=*end).contains(&port.port_number)))
+			&& self.service.as_ref().is_none_or(|x| Some(x) == service.as_ref())
+	}
+}
+
+/// Reads a file and parses it with `parse`, saying which file any error came from.
+pub fn load<T>(path : &str, parse : impl Fn(&str) -> Result<T,String>) -> Result<T,String> {
+	match fs::read_to_string(path) {
+		Ok(x) => parse(&x).map_err(|e| format!("{} in '{}'", e, path)),
+		Err(e) => Err(format!("couldn't read '{}': {}", path, e))
+	}
+}
+
+/// Calls `parse` with every line of a file that isn't blank, along with its line number. Anything after a '#' is a comment.
+pub fn parse_lines(content : &str, mut parse : impl FnMut(&str, usize) -> Result<(),String>) -> Result<(),String> {
+	for (number, line) in content.lines().enumerate() {
+		let line = line.split('#').next().unwrap_or("").trim();
+		if line.is_empty() { continue; }
+		
+		parse(line, number + 1).map_err(|e| format!("{} on line {}", e, number + 1))?;
+	}
+	
+	Ok(())
+}
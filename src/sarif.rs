@@ -0,0 +1,237 @@
+use nmap_xml_parser::port::Port;
+
+use crate::host::AddressesWrapper;
+use crate::host::Change;
+use crate::host::HostDelta;
+use crate::host::HostWrapper;
+use crate::notify::json_string;
+use crate::policy::PolicyReport;
+use crate::severity::PortEvent;
+use crate::severity::Severity;
+use crate::severity::SeverityRules;
+use crate::severity::port_event;
+
+/// The kinds of findings, as (id, name, description). Each kind is written out as one rule per severity, since code scanning
+/// dashboards only read security-severity from rules.
+const RULES : [(&str, &str, &str); 3] = [
+	("NDIFF001", "PortOpened", "A port became open between the two scans."),
+	("NDIFF002", "ServiceNameChanged", "Nmap identified a port as a different service between the two scans."),
+	("NDIFF003", "PolicyViolation", "A host or port violates the policy.")
+];
+
+const SEVERITIES : [Severity; 5] = [Severity::Info, Severity::Low, Severity::Medium, Severity::High, Severity::Critical];
+
+/// A single finding, before it's written out as a SARIF result.
+struct Finding {
+	rule: usize,
+	severity: Severity,
+	message: String,
+	host: String,
+	/// The host's IP addresses, which identify the finding across scans even if the hostname changes.
+	addresses: Vec<String>,
+	port: Option<(String,u16)>,
+	/// The policy rule which was violated, so that violations of different rules on the same port are told apart.
+	policy_rule: Option<String>
+}
+
+/// Builds a SARIF 2.1.0 log from the deltas between two scans and the policy report, if there is one. Every port that became
+/// open and every port identified as a different service is a result, with its severity taken from the rules, and so is every policy violation.
+/// Results are located in the right-hand scan file, and logically on the host and port they're about.
+pub fn to_sarif(scan_path : &str, deltas : &[HostDelta], rules : &SeverityRules, min_severity : Option<Severity>, policy : Option<&PolicyReport>) -> String {
+	let mut findings : Vec<Finding> = Vec::new();
+	
+	for delta in deltas {
+		let title = HostWrapper(delta.host().clone()).get_title();
+		let addresses = AddressesWrapper(delta.host().addresses().cloned().collect()).sorted_ips();
+		for change in delta.port_changes() {
+			let (port, event) = match port_event(&change) {
+				Some(x) => x,
+				None => continue
+			};
+			let severity = match rules.port_severity(&change) {
+				Some(x) if min_severity.is_none_or(|min| x >= min) => x,
+				_ => continue
+			};
+			
+			let finding = match (&change, event) {
+				(_, PortEvent::Opened) => Finding {
+					rule: 0,
+					severity,
+					message: format!("{} {} opened{} on {}", port.protocol, port.port_number, service_suffix(port), title),
+					host: title.clone(),
+					addresses: addresses.clone(),
+					port: Some((port.protocol.to_string(), port.port_number)),
+					policy_rule: None
+				},
+				(Change::Changed(left, right), _) if service_name(left) != service_name(right) => Finding {
+					rule: 1,
+					severity,
+					message: format!("{} {} changed from {} to {} on {}", port.protocol, port.port_number, service_name(left), service_name(right), title),
+					host: title.clone(),
+					addresses: addresses.clone(),
+					port: Some((port.protocol.to_string(), port.port_number)),
+					policy_rule: None
+				},
+				_ => continue
+			};
+			findings.push(finding);
+		}
+	}
+	
+	if let Some(policy) = policy {
+		for (rule, violations) in &policy.results {
+			for violation in violations {
+				findings.push(Finding {
+					rule: 2,
+					severity: Severity::High,
+					message: format!("{} violates '{}'", violation, rule),
+					host: violation.host.clone(),
+					addresses: violation.addresses.clone(),
+					port: violation.port.as_ref().map(|x| (x.protocol.to_string(), x.port_number)),
+					policy_rule: Some(rule.clone())
+				});
+			}
+		}
+	}
+	
+	let rules_json : Vec<String> = RULES.iter().flat_map(|(id, name, description)| SEVERITIES.iter().map(move |severity| format!(
+		"{{\"id\":{},\"name\":{},\"shortDescription\":{{\"text\":{}}},\"defaultConfiguration\":{{\"level\":{}}},\"properties\":{{\"security-severity\":{}}}}}",
+		json_string(&rule_id(id, *severity)), json_string(name), json_string(description), json_string(level(*severity)), json_string(security_severity(*severity))
+	))).collect();
+	let results_json : Vec<String> = findings.iter().map(|x| x.to_json(scan_path)).collect();
+	
+	format!(
+		"{{\"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",\"version\":\"2.1.0\",\"runs\":[{{\"tool\":{{\"driver\":{{\"name\":\"ndiff-rs\",\"version\":{},\"rules\":[{}]}}}},\"results\":[\n{}\n]}}]}}\n",
+		json_string(env!("CARGO_PKG_VERSION")), rules_json.join(","), results_json.join(",\n")
+	)
+}
+
+impl Finding {
+	fn to_json(&self, scan_path : &str) -> String {
+		let location = match &self.port {
+			Some((protocol, port)) => format!("{} {}/{}", self.host, protocol, port),
+			None => self.host.clone()
+		};
+		// Fingerprints leave out the hostname and the severity, so that a finding stays the same when either of them changes.
+		let mut fingerprint = format!("{}:{}", RULES[self.rule].0, self.addresses.join(","));
+		if let Some((protocol, port)) = &self.port { fingerprint.push_str(&format!(":{}/{}", protocol, port)); }
+		if let Some(rule) = &self.policy_rule { fingerprint.push_str(&format!(":{}", rule)); }
+		
+		format!(
+			"{{\"ruleId\":{},\"ruleIndex\":{},\"level\":{},\"message\":{{\"text\":{}}},\"locations\":[{{\"physicalLocation\":{{\"artifactLocation\":{{\"uri\":{}}}}},\"logicalLocations\":[{{\"fullyQualifiedName\":{},\"kind\":\"resource\"}}]}}],\"partialFingerprints\":{{\"ndiffLocation/v1\":{}}},\"properties\":{{\"severity\":{}}}}}",
+			json_string(&rule_id(RULES[self.rule].0, self.severity)), self.rule * SEVERITIES.len() + self.severity as usize, json_string(level(self.severity)), json_string(&self.message), json_string(&scan_path.replace('\\', "/")),
+			json_string(&location), json_string(&fingerprint), json_string(&self.severity.to_string())
+		)
+	}
+}
+
+/// The id of the rule for a kind of finding at a particular severity, e.g. "NDIFF001/high".
+fn rule_id(id : &str, severity : Severity) -> String {
+	format!("{}/{}", id, severity)
+}
+
+/// SARIF only has three levels, so the severities are folded into them.
+fn level(severity : Severity) -> &'static str {
+	match severity {
+		Severity::Critical | Severity::High => "error",
+		Severity::Medium => "warning",
+		Severity::Low | Severity::Info => "note"
+	}
+}
+
+/// The CVSS-like score that code scanning dashboards use to rank security results.
+fn security_severity(severity : Severity) -> &'static str {
+	match severity {
+		Severity::Critical => "9.5",
+		Severity::High => "8.0",
+		Severity::Medium => "5.5",
+		Severity::Low => "3.0",
+		Severity::Info => "0.0"
+	}
+}
+
+fn service_name(port : &Port) -> String {
+	port.service_info.as_ref().map(|x| x.name.clone()).unwrap_or("unknown".to_string())
+}
+
+fn service_suffix(port : &Port) -> String {
+	match &port.service_info {
+		Some(service) => format!(" ({})", service.name),
+		None => String::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	use crate::host::DiffOptions;
+	use crate::policy::Policy;
+	
+	/// Diffs a scan of the gateway against one where it's called `hostname`, has telnet and 8080 open, and identifies 80 as a
+	/// proxy.
+	fn diff(hostname : &str) -> Vec<HostDelta> {
+		let left = scan(&host(&["10.0.0.1", "2001:db8::1"], Some("gw.example"), &[service(22, "ssh", "OpenSSH", "9.6"), service(80, "http", "nginx", "1.24")].concat()));
+		let right = scan(&host(&["2001:db8::1", "10.0.0.1"], Some(hostname), &[service(22, "ssh", "OpenSSH", "9.6"), service(23, "telnet", "Linux telnetd", ""), service(80, "http-proxy", "Squid", "6.6"), port(8080, "open")].concat()));
+		HostDelta::from_scans(&left, &right, &DiffOptions::default())
+	}
+	
+	/// Returns each result's rule id, rule index, level and fingerprint. Results are written one per line.
+	fn results(sarif : &str) -> Vec<(String, String, String, String)> {
+		let field = |line : &str, name : &str| {
+			let value = &line[line.find(&format!("\"{}\":", name)).unwrap() + name.len() + 3..];
+			match value.strip_prefix('"') {
+				Some(x) => x[..x.find('"').unwrap()].to_string(),
+				None => value[..value.find([',', '}']).unwrap()].to_string()
+			}
+		};
+		sarif.lines().filter(|x| x.starts_with("{\"ruleId\"")).map(|x| (field(x, "ruleId"), field(x, "ruleIndex"), field(x, "level"), field(x, "ndiffLocation/v1"))).collect()
+	}
+	
+	fn result(rule : &str, index : usize, level : &str, fingerprint : &str) -> (String, String, String, String) {
+		(rule.to_string(), index.to_string(), level.to_string(), fingerprint.to_string())
+	}
+	
+	#[test]
+	fn maps_findings_to_rules_and_levels() {
+		let rules = SeverityRules::parse("critical service=telnet\ninfo port=8080\nlow port=80").unwrap();
+		let policy = Policy::parse("no-open port=23").unwrap().check(&diff("gw.example"), |_, _| false);
+		let sarif = to_sarif("scans/b.xml", &diff("gw.example"), &rules, None, Some(&policy));
+		
+		assert_eq!(results(&sarif), vec![
+			result("NDIFF002/low", 6, "note", "NDIFF002:10.0.0.1,2001:db8::1:tcp/80"),
+			result("NDIFF001/critical", 4, "error", "NDIFF001:10.0.0.1,2001:db8::1:tcp/23"),
+			result("NDIFF001/info", 0, "note", "NDIFF001:10.0.0.1,2001:db8::1:tcp/8080"),
+			result("NDIFF003/high", 13, "error", "NDIFF003:10.0.0.1,2001:db8::1:tcp/23:no-open port=23")
+		]);
+		assert!(sarif.contains("\"text\":\"tcp 80 changed from http to http-proxy on gw.example (10.0.0.1, 2001:db8::1)\""), "{}", sarif);
+		assert!(sarif.contains("\"artifactLocation\":{\"uri\":\"scans/b.xml\"}"), "{}", sarif);
+		// Every kind of finding has a rule for every severity, in order, so that the rule indexes line up.
+		assert_eq!(sarif.matches("\"security-severity\"").count(), RULES.len() * SEVERITIES.len());
+		assert!(sarif.contains("{\"id\":\"NDIFF003/high\",\"name\":\"PolicyViolation\""), "{}", sarif);
+	}
+	
+	#[test]
+	fn leaves_out_findings_below_the_minimum_severity() {
+		let rules = SeverityRules::parse("critical service=telnet\ninfo port=8080\nlow port=80").unwrap();
+		let rule_ids = |min : Severity| results(&to_sarif("b.xml", &diff("gw.example"), &rules, Some(min), None)).into_iter().map(|x| x.0).collect::<Vec<String>>();
+		
+		assert_eq!(rule_ids(Severity::Low), vec!["NDIFF002/low", "NDIFF001/critical"]);
+		assert_eq!(rule_ids(Severity::High), vec!["NDIFF001/critical"]);
+		assert_eq!(rule_ids(Severity::Info).len(), 3);
+	}
+	
+	#[test]
+	fn keeps_fingerprints_when_the_hostname_or_severity_changes() {
+		let fingerprints = |hostname : &str, rules : &str| {
+			let rules = SeverityRules::parse(rules).unwrap();
+			results(&to_sarif("b.xml", &diff(hostname), &rules, None, None)).into_iter().map(|x| x.3).collect::<Vec<String>>()
+		};
+		
+		let original = fingerprints("gw.example", "");
+		assert_eq!(original.len(), 3);
+		assert_eq!(fingerprints("router.example", ""), original);
+		assert_eq!(fingerprints("gw.example", "critical port=1-65535"), original);
+		assert!(!to_sarif("b.xml", &diff("router.example"), &SeverityRules::default(), None, None).contains("gw.example"));
+	}
+}
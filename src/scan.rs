@@ -0,0 +1,243 @@
+use std::fs;
+use std::fmt;
+
+use nmap_xml_parser::NmapResults;
+
+use crate::details::ScanDetails;
+use crate::details::coverage_difference;
+use crate::details::format_ranges;
+use crate::host::format_timestamp;
+
+/// A parsed Nmap scan, along with the details that nmap_xml_parser doesn't expose.
+#[derive(Debug,Clone)]
+pub struct Scan {
+	pub results: NmapResults,
+	pub details: ScanDetails
+}
+
+#[derive(Debug)]
+pub enum Error {
+	FileRead(std::io::Error),
+	FileParse(nmap_xml_parser::Error)
+}
+
+impl Scan {
+	pub fn load(path : &str) -> Result<Scan,Error> {
+		let content = match fs::read_to_string(path) {
+			Ok(x) => x,
+			Err(e) => return Err(Error::FileRead(e))
+		};
+
+		Scan::parse(&content)
+	}
+
+	pub fn parse(content : &str) -> Result<Scan,Error> {
+		let results = match NmapResults::parse(content) {
+			Ok(x) => x,
+			Err(e) => return Err(Error::FileParse(e))
+		};
+
+		let details = match ScanDetails::parse(content) {
+			Ok(x) => x,
+			Err(e) => return Err(Error::FileParse(e))
+		};
+
+		Ok(Scan { results, details })
+	}
+	
+	/// Returns the targets from the scan's command line, sorted so that two scans of the same targets can be matched up.
+	pub fn targets(&self) -> Vec<String> {
+		let (options, mut output) = self.arguments();
+		// Scans of the targets in a list file are told apart by the name of the file.
+		for (option, value) in options {
+			if option.trim_start_matches('-') == "iL" && let Some(value) = value { output.push(format!("-iL {}", value)); }
+		}
+		
+		output.sort();
+		output
+	}
+	
+	/// Splits the scan's command line up into options, along with the values of those which take them as separate arguments,
+	/// and targets.
+	fn arguments(&self) -> (Vec<(String,Option<String>)>, Vec<String>) {
+		let args = self.details.args.clone().unwrap_or_default();
+		let mut options : Vec<(String,Option<String>)> = Vec::new();
+		let mut targets : Vec<String> = Vec::new();
+		let mut tokens = args.split_whitespace().skip(1);
+		
+		while let Some(token) = tokens.next() {
+			match token.starts_with('-') {
+				// Nmap takes long options with either one dash or two, so they're compared without the dashes.
+				true => match VALUE_OPTIONS.contains(&token.trim_start_matches('-')) {
+					true => options.push((token.to_string(), tokens.next().map(|x| x.to_string()))),
+					false => options.push((token.to_string(), None))
+				},
+				false => targets.push(token.to_string())
+			}
+		}
+		
+		(options, targets)
+	}
+}
+
+/// Nmap options which take their value as a separate argument, so that it isn't mistaken for a target. Values given with '='
+/// or attached to a short option are part of the same argument, so they don't need to be listed.
+const VALUE_OPTIONS : [&str; 65] = [
+	"p", "e", "S", "D", "g", "b", "M", "o", "m", "iL", "iR", "sI", "oN", "oX", "oG", "oA", "oS", "oM", "oH", "exclude",
+	"excludefile", "exclude-ports", "top-ports", "port-ratio", "source-port", "script", "script-args", "script-args-file",
+	"script-help", "script-timeout", "version-intensity", "max-os-tries", "max-retries", "host-timeout", "min-rate", "max-rate",
+	"min-hostgroup", "max-hostgroup", "min-parallelism", "max-parallelism", "min-rtt-timeout", "max-rtt-timeout",
+	"initial-rtt-timeout", "scan-delay", "max-scan-delay", "timing", "scanflags", "data", "data-string", "data-length",
+	"ip-options", "ttl", "mtu", "spoof-mac", "proxies", "proxy", "dns-servers", "stylesheet", "datadir", "servicedb",
+	"versiondb", "resume", "stats-every", "nsock-engine", "route-dst"
+];
+
+/// A side-by-side comparison of the metadata of two scans, with warnings about differences that could make the diff misleading.
+#[derive(Debug,Clone)]
+pub struct ScanComparison {
+	pub rows: Vec<(String,String,String)>,
+	pub warnings: Vec<String>
+}
+
+impl ScanComparison {
+	pub fn from_scans(left : &Scan, right : &Scan) -> ScanComparison {
+		let unknown = || "<unknown>".to_string();
+		let scanner = |scan : &Scan| format!("{} {}", scan.details.scanner.clone().unwrap_or("nmap".to_string()), scan.details.version.clone().unwrap_or_else(unknown));
+		let scan_types = |scan : &Scan| match scan.details.scan_info.is_empty() {
+			true => unknown(),
+			false => scan.details.scan_info.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ")
+		};
+		let end_time = |scan : &Scan| scan.results.scan_end_time.map(format_timestamp).unwrap_or_else(unknown);
+		let elapsed = |scan : &Scan| scan.details.run_stats.elapsed.map(|x| format!("{:.2}s", x)).unwrap_or_else(unknown);
+		
+		let rows = vec![
+			("Scanner".to_string(), scanner(left), scanner(right)),
+			("Command".to_string(), left.details.args.clone().unwrap_or_else(unknown), right.details.args.clone().unwrap_or_else(unknown)),
+			("Scan types".to_string(), scan_types(left), scan_types(right)),
+			("Start time".to_string(), format_timestamp(left.results.scan_start_time), format_timestamp(right.results.scan_start_time)),
+			("End time".to_string(), end_time(left), end_time(right)),
+			("Elapsed".to_string(), elapsed(left), elapsed(right)),
+			("Hosts".to_string(), left.details.run_stats.to_string(), right.details.run_stats.to_string())
+		];
+		
+		ScanComparison { rows, warnings: ScanComparison::warnings(left, right) }
+	}
+	
+	/// Describes differences in how the two scans were run which could show up as changes in the diff.
+	fn warnings(left : &Scan, right : &Scan) -> Vec<String> {
+		let mut warnings : Vec<String> = Vec::new();
+		
+		let scan_types = |scan : &Scan| scan.details.scan_info.iter().map(|x| x.to_string()).collect::<Vec<String>>();
+		if scan_types(left) != scan_types(right) {
+			warnings.push("the scans used different scan types".to_string());
+		}
+		
+		for (protocol, ranges) in coverage_difference(&left.details.scan_info, &right.details.scan_info) {
+			warnings.push(format!("only the left scan covered {} ports {}", protocol, format_ranges(&ranges)));
+		}
+		for (protocol, ranges) in coverage_difference(&right.details.scan_info, &left.details.scan_info) {
+			warnings.push(format!("only the right scan covered {} ports {}", protocol, format_ranges(&ranges)));
+		}
+		
+		let left_options = command_options(left);
+		let right_options = command_options(right);
+		let only_left : Vec<&String> = left_options.iter().filter(|x| !right_options.contains(x)).collect();
+		let only_right : Vec<&String> = right_options.iter().filter(|x| !left_options.contains(x)).collect();
+		if !only_left.is_empty() || !only_right.is_empty() {
+			let list = |options : Vec<&String>| match options.is_empty() {
+				true => "<none>".to_string(),
+				false => options.iter().map(|x| x.as_str()).collect::<Vec<&str>>().join(" ")
+			};
+			warnings.push(format!("the scans were run with different options (only left: {}, only right: {})", list(only_left), list(only_right)));
+		}
+		
+		warnings
+	}
+}
+
+/// Returns the options from a scan's command line along with their values, ignoring output options (which don't affect the
+/// results) and ports (which are covered by the scan info instead).
+fn command_options(scan : &Scan) -> Vec<String> {
+	scan.arguments().0.into_iter()
+		.filter(|(option, _)| !is_output_option(option) && !is_port_option(option))
+		.map(|(option, value)| match value {
+			Some(value) => format!("{} {}", option, value),
+			None => option
+		})
+		.collect()
+}
+
+fn is_output_option(option : &str) -> bool {
+	["o", "m", "oN", "oX", "oG", "oA", "oS", "oM", "oH"].contains(&option.trim_start_matches('-'))
+}
+
+/// Checks for `-p`, either on its own or with the ports attached (e.g. "-p-" or "-p22,80").
+fn is_port_option(option : &str) -> bool {
+	match option.strip_prefix("-p") {
+		Some(rest) => rest.is_empty() || rest.starts_with(|c : char| c.is_ascii_digit() || "-TUSP:,".contains(c)),
+		None => false
+	}
+}
+
+impl fmt::Display for ScanComparison {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "[=] Scan Metadata")?;
+		for (label, left, right) in &self.rows {
+			match left == right {
+				true => writeln!(f, "| {}: {}", label, left)?,
+				false => writeln!(f, "| {}: {} => {}", label, left, right)?
+			};
+		}
+		for warning in &self.warnings {
+			writeln!(f, "Warning: {}", warning)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	
+	/// A scan run with the given command line, covering the given TCP ports with the given scan type.
+	fn run(args : &str, scan_type : &str, services : &str) -> Scan {
+		let scaninfo = format!(r#"<scaninfo type="{}" protocol="tcp" numservices="1000" services="{}"/>"#, scan_type, services);
+		Scan::parse(&nmaprun(args, 1700000000, &scaninfo)).unwrap()
+	}
+	
+	#[test]
+	fn warns_about_different_scan_types_and_coverage() {
+		let left = run("nmap -sS 10.0.0.0/24", "syn", "1-1000");
+		
+		assert_eq!(ScanComparison::warnings(&left, &run("nmap -sS 10.0.0.0/24", "syn", "1-1000")), Vec::<String>::new());
+		assert_eq!(ScanComparison::warnings(&left, &run("nmap -sS 10.0.0.0/24", "connect", "1-1000")), vec!["the scans used different scan types"]);
+		assert_eq!(ScanComparison::warnings(&left, &run("nmap -sS 10.0.0.0/24", "syn", "22,80,1000-2000")), vec![
+			"only the left scan covered tcp ports 1-21,23-79,81-999",
+			"only the right scan covered tcp ports 1001-2000"
+		]);
+	}
+	
+	#[test]
+	fn warns_about_different_options() {
+		let warnings = |left : &str, right : &str| ScanComparison::warnings(&run(left, "syn", "1-1000"), &run(right, "syn", "1-1000"));
+		
+		// Options are compared along with their values.
+		assert_eq!(warnings("nmap --script vuln 10.0.0.0/24", "nmap --script default 10.0.0.0/24"),
+			vec!["the scans were run with different options (only left: --script vuln, only right: --script default)"]);
+		assert_eq!(warnings("nmap -sV -T4 10.0.0.0/24", "nmap -T4 10.0.0.0/24"),
+			vec!["the scans were run with different options (only left: -sV, only right: <none>)"]);
+		// Output files and ports don't change what's found, or are warned about through the coverage instead.
+		assert_eq!(warnings("nmap -oX monday.xml -p 1-1000 10.0.0.0/24", "nmap -oX tuesday.xml -p- 10.0.0.0/24"), Vec::<String>::new());
+		assert_eq!(warnings("nmap -oA monday -p22,80 10.0.0.0/24", "nmap -oA tuesday 10.0.0.0/24"), Vec::<String>::new());
+	}
+	
+	#[test]
+	fn finds_targets_on_the_command_line() {
+		let targets = |args : &str| run(args, "syn", "1-1000").targets();
+		
+		assert_eq!(targets("nmap -sS -p 22 --script vuln 10.0.0.0/24 gw.example"), vec!["10.0.0.0/24", "gw.example"]);
+		assert_eq!(targets("nmap gw.example -oX out.xml 10.0.0.0/24"), targets("nmap -oX other.xml 10.0.0.0/24 gw.example"));
+		assert_eq!(targets("nmap -iL hosts.txt -T4"), vec!["-iL hosts.txt"]);
+	}
+}
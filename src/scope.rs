@@ -0,0 +1,184 @@
+use std::fs;
+use std::fmt;
+use std::str::FromStr;
+use std::net::IpAddr;
+
+use nmap_xml_parser::host::Host;
+use nmap_xml_parser::host::Address;
+
+/// An IPv4 or IPv6 network in CIDR notation. A bare address is treated as a network containing only that address. IPv4
+/// networks also contain the IPv4-mapped IPv6 forms of their addresses.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Cidr {
+	pub network: IpAddr,
+	pub prefix: u8
+}
+
+impl Cidr {
+	pub fn contains(&self, address : &IpAddr) -> bool {
+		match (self.network, address) {
+			(IpAddr::V4(network), IpAddr::V4(address)) => {
+				let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+				u32::from(network) & mask == u32::from(*address) & mask
+			},
+			(IpAddr::V6(network), IpAddr::V6(address)) => {
+				let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+				u128::from(network) & mask == u128::from(*address) & mask
+			},
+			// Nmap reports IPv4 hosts scanned over IPv6 with IPv4-mapped addresses, e.g. "::ffff:10.0.0.1".
+			(IpAddr::V4(_), IpAddr::V6(address)) => address.to_ipv4_mapped().is_some_and(|x| self.contains(&IpAddr::V4(x))),
+			_ => false
+		}
+	}
+}
+
+impl FromStr for Cidr {
+	type Err = String;
+	
+	fn from_str(s : &str) -> Result<Self,Self::Err> {
+		let (address, prefix) = match s.split_once('/') {
+			Some((address, prefix)) => (address, Some(prefix)),
+			None => (s, None)
+		};
+		
+		let network : IpAddr = match address.parse() {
+			Ok(x) => x,
+			Err(_) => return Err(format!("invalid address '{}'", address))
+		};
+		let max_prefix : u8 = match network { IpAddr::V4(_) => 32, IpAddr::V6(_) => 128 };
+		let prefix = match prefix {
+			Some(x) => match x.parse::<u8>() {
+				Ok(x) if x <= max_prefix => x,
+				_ => return Err(format!("invalid prefix length '{}' in '{}'", x, s))
+			},
+			None => max_prefix
+		};
+		
+		Ok(Cidr { network, prefix })
+	}
+}
+
+/// Limits a diff to the hosts within some networks. A host is in scope if any of its IP addresses are in an included network
+/// (or nothing was included) and none of them are in an excluded network.
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Scope {
+	pub include: Vec<Cidr>,
+	pub exclude: Vec<Cidr>
+}
+
+impl Scope {
+	pub fn contains(&self, host : &Host) -> bool {
+		let ips : Vec<&IpAddr> = host.addresses().filter_map(|x| match x { Address::IpAddr(ip) => Some(ip), Address::MacAddr(_) => None }).collect();
+		let within = |networks : &[Cidr]| ips.iter().any(|ip| networks.iter().any(|network| network.contains(ip)));
+		
+		(self.include.is_empty() || within(&self.include)) && !within(&self.exclude)
+	}
+}
+
+/// Parses a list of networks separated by commas or whitespace.
+pub fn parse_list(list : &str) -> Result<Vec<Cidr>,String> {
+	list.split(|x : char| x == ',' || x.is_whitespace()).filter(|x| !x.is_empty()).map(Cidr::from_str).collect()
+}
+
+/// Reads networks from a file, one or more per line. Anything after a '#' is a comment.
+pub fn load_list(path : &str) -> Result<Vec<Cidr>,String> {
+	let content = match fs::read_to_string(path) {
+		Ok(x) => x,
+		Err(e) => return Err(format!("couldn't read '{}': {}", path, e))
+	};
+	
+	let mut output : Vec<Cidr> = Vec::new();
+	for (number, line) in content.lines().enumerate() {
+		let line = line.split('#').next().unwrap_or("");
+		match parse_list(line) {
+			Ok(x) => output.extend(x),
+			Err(e) => return Err(format!("{} (line {} of '{}')", e, number + 1, path))
+		}
+	}
+	
+	Ok(output)
+}
+
+impl fmt::Display for Cidr {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}/{}", self.network, self.prefix)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	
+	fn cidr(s : &str) -> Cidr {
+		s.parse().unwrap()
+	}
+	
+	fn ip(s : &str) -> IpAddr {
+		s.parse().unwrap()
+	}
+	
+	#[test]
+	fn parses_networks() {
+		assert_eq!(cidr("10.0.0.0/8"), Cidr { network: ip("10.0.0.0"), prefix: 8 });
+		assert_eq!(cidr("10.0.0.1"), Cidr { network: ip("10.0.0.1"), prefix: 32 });
+		assert_eq!(cidr("2001:db8::1"), Cidr { network: ip("2001:db8::1"), prefix: 128 });
+		assert_eq!(cidr("2001:db8::/32").to_string(), "2001:db8::/32");
+		assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+		assert!("2001:db8::/129".parse::<Cidr>().is_err());
+		assert!("10.0.0.0/".parse::<Cidr>().is_err());
+		assert!("10.0.0/24".parse::<Cidr>().is_err());
+		assert!("gw.example".parse::<Cidr>().is_err());
+		
+		assert_eq!(parse_list("10.0.0.0/24, 10.0.1.0/24\t2001:db8::/32,"), Ok(vec![cidr("10.0.0.0/24"), cidr("10.0.1.0/24"), cidr("2001:db8::/32")]));
+		assert_eq!(parse_list(""), Ok(vec![]));
+	}
+	
+	#[test]
+	fn matches_addresses_within_networks() {
+		assert!(cidr("10.0.0.0/24").contains(&ip("10.0.0.255")));
+		assert!(!cidr("10.0.0.0/24").contains(&ip("10.0.1.0")));
+		// The host bits of the network don't matter.
+		assert!(cidr("10.0.0.77/24").contains(&ip("10.0.0.1")));
+		assert!(cidr("10.0.0.1/32").contains(&ip("10.0.0.1")));
+		assert!(!cidr("10.0.0.1/32").contains(&ip("10.0.0.2")));
+		assert!(cidr("2001:db8::/32").contains(&ip("2001:db8:ffff::1")));
+		assert!(!cidr("2001:db8::/32").contains(&ip("2001:db9::1")));
+		assert!(cidr("2001:db8::1/128").contains(&ip("2001:db8::1")));
+		assert!(!cidr("2001:db8::1/128").contains(&ip("2001:db8::2")));
+	}
+	
+	#[test]
+	fn matches_everything_in_the_same_family_with_a_zero_prefix() {
+		assert!(cidr("0.0.0.0/0").contains(&ip("255.255.255.255")));
+		assert!(cidr("0.0.0.0/0").contains(&ip("0.0.0.0")));
+		assert!(!cidr("0.0.0.0/0").contains(&ip("2001:db8::1")));
+		assert!(cidr("::/0").contains(&ip("2001:db8::1")));
+		assert!(!cidr("::/0").contains(&ip("10.0.0.1")));
+	}
+	
+	#[test]
+	fn matches_ipv4_mapped_addresses_against_ipv4_networks() {
+		assert!(cidr("10.0.0.0/24").contains(&ip("::ffff:10.0.0.1")));
+		assert!(!cidr("10.0.0.0/24").contains(&ip("::ffff:10.0.1.1")));
+		assert!(cidr("::ffff:0:0/96").contains(&ip("::ffff:10.0.0.1")));
+		// Only mapped addresses count, not the deprecated IPv4-compatible ones.
+		assert!(!cidr("10.0.0.0/24").contains(&ip("::10.0.0.1")));
+	}
+	
+	#[test]
+	fn scopes_hosts_by_any_of_their_addresses() {
+		let scan = scan(&[
+			host(&["10.0.0.1", "2001:db8::1", "00:11:22:33:44:55"], None, ""),
+			host(&["10.0.1.1"], None, ""),
+			host(&["00:11:22:33:44:66"], None, "")
+		].concat());
+		let in_scope = |scope : &Scope| scan.results.hosts().map(|x| scope.contains(x)).collect::<Vec<bool>>();
+		
+		assert_eq!(in_scope(&Scope::default()), vec![true, true, true]);
+		assert_eq!(in_scope(&Scope { include: parse_list("2001:db8::/32").unwrap(), exclude: vec![] }), vec![true, false, false]);
+		assert_eq!(in_scope(&Scope { include: vec![], exclude: parse_list("10.0.1.0/24").unwrap() }), vec![true, false, true]);
+		// A host is out of scope if any of its addresses are excluded, even if another one is included.
+		assert_eq!(in_scope(&Scope { include: parse_list("10.0.0.0/8").unwrap(), exclude: parse_list("2001:db8::1").unwrap() }), vec![false, true, false]);
+	}
+}
@@ -0,0 +1,320 @@
+use std::fmt;
+use std::str::FromStr;
+
+use nmap_xml_parser::port::Port;
+use nmap_xml_parser::port::PortState;
+
+use crate::host::Change;
+use crate::host::HostDelta;
+use crate::rulefile;
+use crate::rulefile::PortFilter;
+
+/// How much a change matters, from least to most important.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Severity {
+	Info,
+	Low,
+	Medium,
+	High,
+	Critical
+}
+
+/// What happened to a port, as far as severity rules are concerned.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum PortEvent {
+	/// The port wasn't open on the left side, but is open on the right side.
+	Opened,
+	/// The port was open on the left side, but isn't open on the right side.
+	Closed,
+	/// Any other change, e.g. from closed to filtered, or a different service.
+	Changed
+}
+
+/// A single line of a rules file. Every condition that is set has to match for the rule to apply.
+#[derive(Debug,Clone)]
+pub struct Rule {
+	pub severity: Severity,
+	pub filter: PortFilter,
+	pub event: Option<PortEvent>
+}
+
+/// Assigns severities to port changes. The first rule that matches a change wins, and changes which no rule matches (as well
+/// as changes to anything other than ports) get the default severity.
+#[derive(Debug,Clone)]
+pub struct SeverityRules {
+	pub rules: Vec<Rule>,
+	pub default: Severity
+}
+
+/// The severity of a host delta as a whole, along with the severity of each of its port changes.
+#[derive(Debug,Clone)]
+pub struct Assessment {
+	pub severity: Severity,
+	pub ports: Vec<(String,Severity)>
+}
+
+impl Default for SeverityRules {
+	fn default() -> Self {
+		Self {
+			rules: Vec::new(),
+			default: Severity::Medium
+		}
+	}
+}
+
+impl SeverityRules {
+	pub fn load(path : &str) -> Result<SeverityRules,String> {
+		rulefile::load(path, SeverityRules::parse)
+	}
+	
+	/// Parses a rules file. Each line is a severity followed by conditions, e.g. "critical service=telnet change=opened" or
+	/// "info port=49152-65535", or sets the default severity, e.g. "default low". Anything after a '#' is a comment.
+	pub fn parse(content : &str) -> Result<SeverityRules,String> {
+		let mut output = SeverityRules::default();
+		
+		rulefile::parse_lines(content, |line, _| {
+			let mut words = line.split_whitespace();
+			let first = words.next().unwrap_or_default();
+			match first {
+				"default" => match (words.next(), words.next()) {
+					(Some(x), None) => x.parse().map(|x| output.default = x),
+					_ => Err("expected a single severity after 'default'".to_string())
+				},
+				_ => Rule::parse(first, words).map(|x| output.rules.push(x))
+			}
+		})?;
+		
+		Ok(output)
+	}
+	
+	/// Returns the severity of a single port change, or None if it isn't really a change.
+	pub fn port_severity(&self, change : &Change<Port>) -> Option<Severity> {
+		let (port, event) = port_event(change)?;
+		let severity = match self.rules.iter().find(|rule| rule.matches(port, event)) {
+			Some(rule) => rule.severity,
+			None => self.default
+		};
+		Some(severity)
+	}
+	
+	/// Assesses a host delta, returning None for unchanged hosts. The delta is as severe as its most severe port change, or has
+	/// the default severity if anything other than its ports changed.
+	pub fn assess(&self, delta : &HostDelta) -> Option<Assessment> {
+		let ports : Vec<(String,Severity)> = delta.port_changes().iter().filter_map(|change| {
+			let (port, event) = port_event(change)?;
+			Some((describe(port, event), self.port_severity(change)?))
+		}).collect();
+		
+		let other_changes = match delta {
+			HostDelta::Changed(diff) => diff.status.is_some() || diff.addresses.is_some() || diff.hostnames.is_some() || diff.os.is_some() || diff.reboot.is_some() || diff.route.is_some(),
+			HostDelta::New(_) | HostDelta::Gone(_) => ports.is_empty(),
+			HostDelta::Unchanged(_, _) => return None
+		};
+		
+		let severity = ports.iter().map(|x| x.1).chain(other_changes.then_some(self.default)).max()?;
+		Some(Assessment { severity, ports })
+	}
+}
+
+impl Rule {
+	fn parse<'a>(severity : &str, conditions : impl Iterator<Item = &'a str>) -> Result<Rule,String> {
+		let mut rule = Rule { severity: severity.parse()?, filter: PortFilter::default(), event: None };
+		
+		for condition in conditions {
+			if rule.filter.parse_condition(condition)? { continue; }
+			match condition.split_once('=') {
+				Some(("change", x)) => rule.event = Some(x.parse()?),
+				_ => return Err(format!("unknown condition '{}' (expected proto=, port=, service= or change=)", condition))
+			}
+		}
+		
+		Ok(rule)
+	}
+	
+	fn matches(&self, port : &Port, event : PortEvent) -> bool {
+		self.filter.matches(port) && self.event.is_none_or(|x| x == event)
+	}
+}
+
+/// Works out which port a change is about and what happened to it. Ports that closed are described by their left side, and
+/// everything else by its right side. Ports that only one scan covered are treated like additions and removals, since an open
+/// port that wasn't seen before is still a new exposure.
+pub fn port_event(change : &Change<Port>) -> Option<(&Port, PortEvent)> {
+	let open = |port : &Port| port.status.state == PortState::Open;
+	
+	match change {
+		Change::Added(port) | Change::OnlyScannedRight(port) if open(port) => Some((port, PortEvent::Opened)),
+		Change::Removed(port) | Change::OnlyScannedLeft(port) if open(port) => Some((port, PortEvent::Closed)),
+		Change::Changed(left, right) if !open(left) && open(right) => Some((right, PortEvent::Opened)),
+		Change::Changed(left, right) if open(left) && !open(right) => Some((left, PortEvent::Closed)),
+		Change::Added(port) | Change::Removed(port) | Change::Changed(_, port) => Some((port, PortEvent::Changed)),
+		Change::OnlyScannedLeft(port) | Change::OnlyScannedRight(port) => Some((port, PortEvent::Changed)),
+		Change::Same(_) | Change::Collapsed(_) | Change::Expanded(_) => None
+	}
+}
+
+fn describe(port : &Port, event : PortEvent) -> String {
+	match &port.service_info {
+		Some(service) => format!("{} {} {} ({})", port.protocol, port.port_number, event, service.name),
+		None => format!("{} {} {}", port.protocol, port.port_number, event)
+	}
+}
+
+impl FromStr for Severity {
+	type Err = String;
+	
+	fn from_str(s : &str) -> Result<Self,Self::Err> {
+		match s.to_lowercase().as_str() {
+			"info" => Ok(Severity::Info),
+			"low" => Ok(Severity::Low),
+			"medium" => Ok(Severity::Medium),
+			"high" => Ok(Severity::High),
+			"critical" => Ok(Severity::Critical),
+			_ => Err(format!("unknown severity '{}' (expected info, low, medium, high or critical)", s))
+		}
+	}
+}
+
+impl FromStr for PortEvent {
+	type Err = String;
+	
+	fn from_str(s : &str) -> Result<Self,Self::Err> {
+		match s {
+			"opened" => Ok(PortEvent::Opened),
+			"closed" => Ok(PortEvent::Closed),
+			"changed" => Ok(PortEvent::Changed),
+			_ => Err(format!("unknown change '{}' (expected opened, closed or changed)", s))
+		}
+	}
+}
+
+impl fmt::Display for Severity {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let display_str = match self {
+			Severity::Info => "info",
+			Severity::Low => "low",
+			Severity::Medium => "medium",
+			Severity::High => "high",
+			Severity::Critical => "critical"
+		};
+		write!(f, "{}", display_str)
+	}
+}
+
+impl fmt::Display for PortEvent {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let display_str = match self {
+			PortEvent::Opened => "opened",
+			PortEvent::Closed => "closed",
+			PortEvent::Changed => "changed"
+		};
+		write!(f, "{}", display_str)
+	}
+}
+
+impl fmt::Display for Assessment {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "| Severity: {}", self.severity)?;
+		for (port, severity) in &self.ports {
+			writeln!(f, "|   {}: {}", port, severity)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	use crate::host::AddressesWrapper;
+	use crate::host::DiffOptions;
+	
+	/// Parses port elements on a single host.
+	fn ports(ports : &str) -> Vec<Port> {
+		scan(&host(&["10.0.0.1"], None, ports)).results.hosts().next().unwrap().port_info.ports().cloned().collect()
+	}
+	
+	fn one_port(port : &str) -> Port {
+		ports(port).remove(0)
+	}
+	
+	#[test]
+	fn parses_rules() {
+		let rules = SeverityRules::parse("# Rules\ndefault low\ncritical service=Telnet change=opened # remote shells\n\nINFO port=49152-65535\n").unwrap();
+		assert_eq!(rules.default, Severity::Low);
+		assert_eq!(rules.rules.iter().map(|x| x.severity).collect::<Vec<Severity>>(), vec![Severity::Critical, Severity::Info]);
+		assert_eq!(rules.rules[0].filter.service.as_deref(), Some("telnet"));
+		assert_eq!(rules.rules[0].event, Some(PortEvent::Opened));
+		assert_eq!(rules.rules[1].filter.ports, vec![(49152, 65535)]);
+		assert_eq!(rules.rules[1].event, None);
+		
+		assert_eq!(SeverityRules::parse("").unwrap().default, Severity::Medium);
+		assert_eq!(SeverityRules::parse("urgent port=22").unwrap_err(), "unknown severity 'urgent' (expected info, low, medium, high or critical) on line 1");
+		assert_eq!(SeverityRules::parse("default").unwrap_err(), "expected a single severity after 'default' on line 1");
+		assert_eq!(SeverityRules::parse("default low high").unwrap_err(), "expected a single severity after 'default' on line 1");
+		assert_eq!(SeverityRules::parse("high change=appeared").unwrap_err(), "unknown change 'appeared' (expected opened, closed or changed) on line 1");
+		assert!(SeverityRules::parse("high\nhigh host=gw.example").unwrap_err().starts_with("unknown condition 'host=gw.example'"));
+	}
+	
+	#[test]
+	fn classifies_port_changes() {
+		let (open, closed, filtered) = (one_port(&port(23, "open")), one_port(&port(23, "closed")), one_port(&port(23, "filtered")));
+		let event = |change : Change<Port>| port_event(&change).map(|(port, event)| (port.status.state.to_string(), event));
+		let state = |port : &Port| port.status.state.to_string();
+		
+		assert_eq!(event(Change::Added(open.clone())), Some((state(&open), PortEvent::Opened)));
+		assert_eq!(event(Change::Added(closed.clone())), Some((state(&closed), PortEvent::Changed)));
+		assert_eq!(event(Change::Removed(open.clone())), Some((state(&open), PortEvent::Closed)));
+		assert_eq!(event(Change::Removed(filtered.clone())), Some((state(&filtered), PortEvent::Changed)));
+		// Ports that open are described by their right side, and ports that close by their left side.
+		assert_eq!(event(Change::Changed(closed.clone(), open.clone())), Some((state(&open), PortEvent::Opened)));
+		assert_eq!(event(Change::Changed(open.clone(), filtered.clone())), Some((state(&open), PortEvent::Closed)));
+		assert_eq!(event(Change::Changed(closed.clone(), filtered.clone())), Some((state(&filtered), PortEvent::Changed)));
+		assert_eq!(event(Change::OnlyScannedRight(open.clone())), Some((state(&open), PortEvent::Opened)));
+		assert_eq!(event(Change::OnlyScannedLeft(open.clone())), Some((state(&open), PortEvent::Closed)));
+		assert_eq!(event(Change::OnlyScannedLeft(closed.clone())), Some((state(&closed), PortEvent::Changed)));
+		assert_eq!(event(Change::Same(open.clone())), None);
+		assert_eq!(event(Change::Collapsed(closed.clone())), None);
+		assert_eq!(event(Change::Expanded(filtered)), None);
+	}
+	
+	#[test]
+	fn uses_the_first_matching_rule() {
+		let rules = SeverityRules::parse("critical service=telnet change=opened\nlow port=1-1024\ninfo port=23").unwrap();
+		let telnet = ports(&service(23, "telnet", "Linux telnetd", "")).remove(0);
+		
+		assert_eq!(rules.port_severity(&Change::Added(telnet.clone())), Some(Severity::Critical));
+		// The first rule only matches ports that opened, so the second one wins over the more specific third one.
+		assert_eq!(rules.port_severity(&Change::Removed(telnet.clone())), Some(Severity::Low));
+		assert_eq!(rules.port_severity(&Change::Added(one_port(&port(8080, "open")))), Some(Severity::Medium));
+		assert_eq!(rules.port_severity(&Change::Same(telnet)), None);
+		assert_eq!(SeverityRules::default().port_severity(&Change::Added(one_port(&port(22, "open")))), Some(Severity::Medium));
+	}
+	
+	#[test]
+	fn assesses_hosts_by_their_most_severe_change() {
+		let rules = SeverityRules::parse("default low\ncritical service=telnet change=opened\ninfo change=closed").unwrap();
+		let left = scan(&[
+			host(&["10.0.0.1"], None, &port(80, "open")),
+			host(&["10.0.0.2"], None, &port(80, "open")),
+			host(&["10.0.0.3"], None, &port(22, "open"))
+		].concat());
+		let right = scan(&[
+			host(&["10.0.0.1"], None, &[port(80, "closed"), service(23, "telnet", "Linux telnetd", "")].concat()),
+			host(&["10.0.0.2"], None, &port(80, "filtered")),
+			host(&["10.0.0.3"], None, &port(22, "open")),
+			host(&["10.0.0.4"], None, "")
+		].concat());
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		let assess = |address : &str| deltas.iter().find(|x| AddressesWrapper(x.host().addresses().cloned().collect()).entries() == [address]).and_then(|x| rules.assess(x));
+		
+		let changed = assess("10.0.0.1").unwrap();
+		assert_eq!(changed.severity, Severity::Critical);
+		assert_eq!(changed.ports, vec![("tcp 80 closed".to_string(), Severity::Info), ("tcp 23 opened (telnet)".to_string(), Severity::Critical)]);
+		assert_eq!(assess("10.0.0.2").unwrap().severity, Severity::Info);
+		assert!(assess("10.0.0.3").is_none());
+		// A new host without any ports still gets the default severity.
+		let new = assess("10.0.0.4").unwrap();
+		assert_eq!((new.severity, new.ports.len()), (Severity::Low, 0));
+	}
+}
@@ -0,0 +1,119 @@
+use nmap_xml_parser::host::Address;
+use nmap_xml_parser::port::PortProtocol;
+
+use crate::details::format_ranges;
+use crate::details::to_ranges;
+use crate::host::DeltaKind;
+use crate::host::HostDelta;
+use crate::severity::port_event;
+
+/// The hosts and ports affected by a diff, for launching a scan which re-verifies just those.
+#[derive(Debug,Clone,Default)]
+pub struct TargetList {
+	/// The IPv4 addresses of the hosts, for Nmap's `-iL` option. Nmap can't scan IPv4 and IPv6 targets in the same run, so the
+	/// IPv6 addresses are kept in a separate list.
+	pub ipv4: Vec<String>,
+	pub ipv6: Vec<String>,
+	pub ports: Vec<(PortProtocol,u16)>
+}
+
+impl TargetList {
+	/// Gathers the IP addresses of every host in the deltas, along with every port that changed on them. New and gone hosts
+	/// contribute all of their ports. Changed hosts contribute their addresses from both sides, since a dual-stack host may
+	/// have been scanned over IPv4 on one side and IPv6 on the other.
+	pub fn from_deltas<'a>(deltas : impl Iterator<Item = &'a HostDelta>) -> TargetList {
+		let mut output = TargetList::default();
+		
+		for delta in deltas.filter(|x| x.kind() != DeltaKind::Unchanged) {
+			let (left, right) = delta.sides();
+			let addresses = left.into_iter().chain(right).flat_map(|x| x.addresses());
+			for ip in addresses.filter_map(|x| match x { Address::IpAddr(ip) => Some(ip), Address::MacAddr(_) => None }) {
+				let list = match ip.is_ipv4() {
+					true => &mut output.ipv4,
+					false => &mut output.ipv6
+				};
+				if !list.contains(&ip.to_string()) { list.push(ip.to_string()); }
+			}
+			for change in delta.port_changes() {
+				if let Some((port, _)) = port_event(&change) && !output.ports.contains(&(port.protocol.clone(), port.port_number)) {
+					output.ports.push((port.protocol.clone(), port.port_number));
+				}
+			}
+		}
+		
+		output
+	}
+	
+	/// Splits the deltas up by kind, returning a target list for each kind that has any hosts.
+	pub fn by_kind(deltas : &[HostDelta]) -> Vec<(DeltaKind,TargetList)> {
+		[DeltaKind::New, DeltaKind::Changed, DeltaKind::Gone].into_iter()
+			.map(|kind| (kind, TargetList::from_deltas(deltas.iter().filter(|x| x.kind() == kind))))
+			.filter(|(_, targets)| !targets.is_empty())
+			.collect()
+	}
+	
+	pub fn is_empty(&self) -> bool {
+		self.ipv4.is_empty() && self.ipv6.is_empty()
+	}
+	
+	/// Returns the ports in the format Nmap's `-p` option expects, e.g. "T:22,80-81,U:53", or None if no ports changed. IP
+	/// protocol numbers are left out, since they can't be mixed with ports.
+	pub fn port_spec(&self) -> Option<String> {
+		let mut output : Vec<String> = Vec::new();
+		for (protocol, prefix) in [(PortProtocol::Tcp, "T"), (PortProtocol::Udp, "U"), (PortProtocol::Sctp, "S")] {
+			let mut ports : Vec<u16> = self.ports.iter().filter(|(x, _)| *x == protocol).map(|(_, port)| *port).collect();
+			if ports.is_empty() { continue; }
+			ports.sort();
+			output.push(format!("{}:{}", prefix, format_ranges(&to_ranges(&ports))));
+		}
+		
+		match output.is_empty() {
+			true => None,
+			false => Some(output.join(","))
+		}
+	}
+	
+	/// Returns the scan types needed to scan every protocol in the port spec. Nmap only scans TCP ports by default, and stops
+	/// doing so once any other scan type is given, so a TCP scan is asked for explicitly alongside UDP or SCTP.
+	pub fn scan_types(&self) -> Vec<&'static str> {
+		let has = |protocol : PortProtocol| self.ports.iter().any(|(x, _)| *x == protocol);
+		let (tcp, udp, sctp) = (has(PortProtocol::Tcp), has(PortProtocol::Udp), has(PortProtocol::Sctp));
+		
+		let mut output : Vec<&'static str> = Vec::new();
+		if tcp && (udp || sctp) { output.push("-sS"); }
+		if udp { output.push("-sU"); }
+		if sctp { output.push("-sY"); }
+		output
+	}
+	
+	/// Returns an Nmap command line which re-scans the addresses in a list file, which holds either the IPv4 or the IPv6
+	/// addresses.
+	pub fn command(&self, list : &str, ipv6 : bool) -> String {
+		let mut args : Vec<String> = vec!["nmap".to_string()];
+		if ipv6 { args.push("-6".to_string()); }
+		args.extend(self.scan_types().iter().map(|x| x.to_string()));
+		if let Some(spec) = self.port_spec() { args.extend(["-p".to_string(), spec]); }
+		args.extend(["-iL".to_string(), list.to_string()]);
+		args.join(" ")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	use crate::host::DiffOptions;
+	
+	#[test]
+	fn lists_both_addresses_of_a_changed_dual_stack_host() {
+		let left = scan(&host(&["10.0.0.1"], Some("gw.example"), &port(22, "open")));
+		let right = scan(&host(&["2001:db8::1"], Some("gw.example"), &port(22, "closed")));
+		let deltas = HostDelta::from_scans(&left, &right, &DiffOptions::default());
+		assert_eq!(deltas.len(), 1);
+		
+		let targets = TargetList::from_deltas(deltas.iter());
+		assert_eq!(targets.ipv4, vec!["10.0.0.1"]);
+		assert_eq!(targets.ipv6, vec!["2001:db8::1"]);
+		assert_eq!(targets.port_spec(), Some("T:22".to_string()));
+	}
+}
@@ -0,0 +1,221 @@
+use std::fs;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+
+use notify::Event;
+use notify::EventKind;
+use notify::RecommendedWatcher;
+use notify::RecursiveMode;
+use notify::Watcher as _;
+use notify::event::AccessKind;
+use notify::event::AccessMode;
+
+use crate::scan::Scan;
+
+/// Watches a directory for new Nmap XML files, and pairs each new scan with the previous scan of the same targets.
+pub struct Watcher {
+	directory: PathBuf,
+	/// The file system watcher has to be kept alive for events to keep arriving.
+	_watcher: RecommendedWatcher,
+	events: Receiver<notify::Result<Event>>,
+	seen: HashSet<PathBuf>,
+	/// The most recent scan of each set of targets, keyed by the targets from its command line.
+	latest: HashMap<Vec<String>,(String,Scan)>
+}
+
+/// A scan which appeared in the watched directory, along with the previous scan of the same targets if there was one.
+pub struct NewScan {
+	pub path: String,
+	pub scan: Scan,
+	pub previous: Option<(String,Scan)>,
+	/// Whether a scan of the same targets that was run later had already appeared. Late scans have no previous scan, since
+	/// diffing them against the later one would report every change backwards.
+	pub late: bool
+}
+
+impl Watcher {
+	/// Starts watching a directory. Scans which are already there are only used as the previous scans for new ones.
+	pub fn new(directory : &str) -> Result<Watcher,String> {
+		let (sender, events) = mpsc::channel();
+		let mut watcher = notify::recommended_watcher(sender).map_err(|e| format!("couldn't watch '{}': {}", directory, e))?;
+		watcher.watch(Path::new(directory), RecursiveMode::NonRecursive).map_err(|e| format!("couldn't watch '{}': {}", directory, e))?;
+		
+		let mut output = Watcher { directory: PathBuf::from(directory), _watcher: watcher, events, seen: HashSet::new(), latest: HashMap::new() };
+		// The directory is only watched from here on, so the files already in it are read up front. Any that can't be parsed
+		// are skipped, since they were there before we started.
+		let entries = fs::read_dir(directory).map_err(|e| format!("couldn't read '{}': {}", directory, e))?;
+		output.check(entries.flatten().map(|x| x.path()).collect());
+		Ok(output)
+	}
+	
+	/// Waits until one or more scans have appeared. Files are only picked up once Nmap has finished writing them, and files
+	/// which are complete but can't be parsed are returned as errors.
+	pub fn wait(&mut self) -> Result<Vec<Result<NewScan,String>>,String> {
+		loop {
+			let event = self.events.recv().map_err(|_| format!("stopped watching '{}'", self.directory.display()))?;
+			let mut paths = self.event_paths(event)?;
+			// Nmap writes its output a bit at a time, so every event that's already queued up is handled at once.
+			while let Ok(event) = self.events.try_recv() {
+				paths.extend(self.event_paths(event)?);
+			}
+			
+			let output = self.check(paths);
+			if !output.is_empty() { return Ok(output); }
+		}
+	}
+	
+	/// Returns the files an event is about, if it could mean that a scan has been written.
+	fn event_paths(&self, event : notify::Result<Event>) -> Result<Vec<PathBuf>,String> {
+		let event = event.map_err(|e| format!("couldn't watch '{}': {}", self.directory.display(), e))?;
+		match event.kind {
+			// The paths are rebuilt from the directory as it was given, so they match the paths of the files that were already there.
+			EventKind::Create(_) | EventKind::Modify(_) | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+				Ok(event.paths.iter().filter_map(|x| x.file_name()).map(|x| self.directory.join(x)).collect())
+			},
+			_ => Ok(Vec::new())
+		}
+	}
+	
+	/// Reads any of the files which are finished scans that haven't been seen before.
+	fn check(&mut self, mut paths : Vec<PathBuf>) -> Vec<Result<NewScan,String>> {
+		paths.sort();
+		paths.dedup();
+		
+		let mut output : Vec<Result<NewScan,String>> = Vec::new();
+		let mut scans : Vec<(String,Scan)> = Vec::new();
+		for path in paths {
+			if self.seen.contains(&path) || path.extension().is_none_or(|x| x != "xml") { continue; }
+			
+			// Nmap writes its XML output as it goes, so a file isn't complete until the root element has been closed.
+			let content = match fs::read_to_string(&path) {
+				Ok(x) if x.trim_end().ends_with("</nmaprun>") => x,
+				_ => continue
+			};
+			
+			self.seen.insert(path.clone());
+			match Scan::parse(&content) {
+				Ok(scan) => scans.push((path.display().to_string(), scan)),
+				Err(e) => output.push(Err(format!("Failed to parse '{}': {:?}", path.display(), e)))
+			}
+		}
+		
+		// Several scans of the same targets could appear at once, so they're paired up in the order they were run. A scan that
+		// was run before the latest one doesn't replace it, so that the next scan isn't diffed against an older baseline.
+		scans.sort_by_key(|(_, scan)| scan.results.scan_start_time);
+		for (path, scan) in scans {
+			let late = self.latest.get(&scan.targets()).is_some_and(|(_, latest)| latest.results.scan_start_time > scan.results.scan_start_time);
+			let previous = match late {
+				true => None,
+				false => self.latest.insert(scan.targets(), (path.clone(), scan.clone()))
+			};
+			output.push(Ok(NewScan { path, scan, previous, late }));
+		}
+		
+		output
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fixtures::*;
+	
+	/// A directory of scans which is removed again when the test finishes.
+	struct ScanDirectory(PathBuf);
+	
+	impl ScanDirectory {
+		fn new(name : &str) -> ScanDirectory {
+			let path = std::env::temp_dir().join(format!("ndiff-rs-{}-{}", name, std::process::id()));
+			let _ = fs::remove_dir_all(&path);
+			fs::create_dir_all(&path).unwrap();
+			ScanDirectory(path)
+		}
+		
+		/// Writes a scan of 10.0.0.0/24 that was started at `start`, returning its path.
+		fn write(&self, name : &str, start : i64) -> PathBuf {
+			self.write_raw(name, &nmaprun("nmap 10.0.0.0/24", start, &host(&["10.0.0.1"], None, &open(&[22]))))
+		}
+		
+		fn write_raw(&self, name : &str, content : &str) -> PathBuf {
+			let path = self.0.join(name);
+			fs::write(&path, content).unwrap();
+			path
+		}
+		
+		fn watch(&self) -> Watcher {
+			Watcher::new(&self.0.display().to_string()).unwrap()
+		}
+	}
+	
+	impl Drop for ScanDirectory {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.0);
+		}
+	}
+	
+	fn name(path : &str) -> String {
+		Path::new(path).file_name().unwrap().to_string_lossy().to_string()
+	}
+	
+	/// Returns the name of each new scan, along with the name of its previous scan and whether it was late.
+	fn pairs(output : Vec<Result<NewScan,String>>) -> Vec<(String, Option<String>, bool)> {
+		output.into_iter().map(|x| {
+			let x = x.unwrap();
+			(name(&x.path), x.previous.map(|(path, _)| name(&path)), x.late)
+		}).collect()
+	}
+	
+	#[test]
+	fn waits_for_scans_to_be_complete() {
+		let directory = ScanDirectory::new("complete");
+		let mut watcher = directory.watch();
+		
+		let content = nmaprun("nmap 10.0.0.0/24", 1700000000, &host(&["10.0.0.1"], None, &open(&[22])));
+		let path = directory.write_raw("a.xml", &content[..content.find("<runstats>").unwrap()]);
+		let notes = directory.write_raw("notes.txt", "</nmaprun>");
+		assert!(watcher.check(vec![path.clone(), notes.clone()]).is_empty());
+		
+		directory.write_raw("a.xml", &content);
+		assert_eq!(pairs(watcher.check(vec![path.clone(), notes])), vec![("a.xml".to_string(), None, false)]);
+		// Files are only picked up once, however many events there are for them.
+		assert!(watcher.check(vec![path]).is_empty());
+		
+		let broken = directory.write_raw("b.xml", "<nmaprun></host></nmaprun>");
+		let output = watcher.check(vec![broken]);
+		assert!(output[0].as_ref().is_err_and(|x| x.starts_with("Failed to parse")));
+	}
+	
+	#[test]
+	fn pairs_scans_in_the_order_they_were_run() {
+		let directory = ScanDirectory::new("order");
+		directory.write("existing.xml", 1700000000);
+		let mut watcher = directory.watch();
+		
+		// The second scan sorts first by name, but it was run later.
+		let paths = vec![directory.write("a.xml", 1700172800), directory.write("b.xml", 1700086400)];
+		assert_eq!(pairs(watcher.check(paths)), vec![
+			("b.xml".to_string(), Some("existing.xml".to_string()), false),
+			("a.xml".to_string(), Some("b.xml".to_string()), false)
+		]);
+	}
+	
+	#[test]
+	fn keeps_the_latest_scan_as_the_baseline() {
+		let directory = ScanDirectory::new("baseline");
+		directory.write("existing.xml", 1700086400);
+		let mut watcher = directory.watch();
+		
+		let late = directory.write("late.xml", 1700000000);
+		assert_eq!(pairs(watcher.check(vec![late])), vec![("late.xml".to_string(), None, true)]);
+		let next = directory.write("next.xml", 1700172800);
+		assert_eq!(pairs(watcher.check(vec![next])), vec![("next.xml".to_string(), Some("existing.xml".to_string()), false)]);
+		
+		// Scans of other targets have their own baseline.
+		let other = directory.write_raw("other.xml", &nmaprun("nmap 10.0.1.0/24", 1700000000, ""));
+		assert_eq!(pairs(watcher.check(vec![other])), vec![("other.xml".to_string(), None, false)]);
+	}
+}